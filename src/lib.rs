@@ -0,0 +1,5339 @@
+use anyhow::{anyhow, ensure, Context, Result};
+use rand::Rng;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use sled::Db;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub mod wire;
+
+// -----------------------------
+// Types
+// -----------------------------
+
+/// Account identifier used for transaction endpoints and block miners.
+/// Serialized as a plain string, so the wire format is unchanged, but
+/// only 1 to 64 ASCII letters, digits or `_` are accepted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct Address(String);
+
+impl Address {
+    pub const MAX_LEN: usize = 64;
+
+    pub fn parse(s: &str) -> Result<Self> {
+        ensure!(!s.is_empty(), "address is empty");
+        ensure!(
+            s.len() <= Self::MAX_LEN,
+            "address is {} bytes, the limit is {}",
+            s.len(),
+            Self::MAX_LEN
+        );
+        ensure!(
+            s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'),
+            "address {:?} may only contain ASCII letters, digits and '_'",
+            s
+        );
+        Ok(Self(s.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        Address::parse(&s)
+    }
+}
+
+impl From<Address> for String {
+    fn from(a: Address) -> Self {
+        a.0
+    }
+}
+
+impl std::str::FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Address::parse(s)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub from: Address,
+    pub to: Address,
+    pub amount: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockHeader {
+    /// Soft-fork signaling bits. Omitted from the encoding when zero so
+    /// blocks from before the field existed keep their hashes.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub version: u32,
+    pub parent_hash: String,
+    pub merkle_root: String,
+    pub timestamp: i64,
+    pub nonce: u64,
+    pub difficulty: u32,
+    pub miner: Address,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Block {
+    pub header: BlockHeader,
+    #[serde(deserialize_with = "deserialize_txs")]
+    pub txs: Vec<Transaction>,
+    pub hash: String,
+}
+
+/// Most transactions a block may carry.
+pub const MAX_BLOCK_TXS: usize = 10_000;
+/// Largest serialized block `decode_block` will parse.
+pub const MAX_BLOCK_BYTES: usize = 4 * 1024 * 1024;
+
+/// Reads a transaction list, failing as soon as it passes `MAX_BLOCK_TXS`
+/// instead of growing to whatever length the input claims.
+fn deserialize_txs<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Transaction>, D::Error> {
+    struct TxsVisitor;
+
+    impl<'de> Visitor<'de> for TxsVisitor {
+        type Value = Vec<Transaction>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "at most {} transactions", MAX_BLOCK_TXS)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            if let Some(n) = seq.size_hint().filter(|&n| n > MAX_BLOCK_TXS) {
+                return Err(de::Error::invalid_length(n, &self));
+            }
+            // never trust a claimed length for the allocation
+            let mut txs = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(1024));
+            while let Some(tx) = seq.next_element()? {
+                if txs.len() == MAX_BLOCK_TXS {
+                    return Err(de::Error::invalid_length(MAX_BLOCK_TXS + 1, &self));
+                }
+                txs.push(tx);
+            }
+            Ok(txs)
+        }
+    }
+
+    d.deserialize_seq(TxsVisitor)
+}
+
+/// How decoding treats fields this build does not know.
+///
+/// `Lenient` drops them, so older nodes can read records from newer ones
+/// during a rollout. The dropped data is not covered by anything this
+/// node checks: the block hash is recomputed from the known header fields
+/// only, so two encodings that differ in unknown fields decode to the
+/// same, equally valid block, and whatever a newer node meant by the
+/// extra field is silently ignored here. `Strict` rejects such records,
+/// closing that gap at the cost of refusing every newer format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Decoding {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+impl std::str::FromStr for Decoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lenient" => Ok(Decoding::Lenient),
+            "strict" => Ok(Decoding::Strict),
+            other => Err(anyhow!(
+                "unknown decoding {:?} (expected lenient or strict)",
+                other
+            )),
+        }
+    }
+}
+
+const BLOCK_FIELDS: &[&str] = &["header", "txs", "hash"];
+const HEADER_FIELDS: &[&str] = &[
+    "version",
+    "parent_hash",
+    "merkle_root",
+    "timestamp",
+    "nonce",
+    "difficulty",
+    "miner",
+];
+const TX_FIELDS: &[&str] = &["from", "to", "amount"];
+
+/// Fails on the first key of `value` (when it is an object) that is not
+/// in `known`.
+fn reject_unknown_fields(value: &serde_json::Value, known: &[&str], what: &str) -> Result<()> {
+    if let Some(object) = value.as_object() {
+        if let Some(key) = object.keys().find(|k| !known.contains(&k.as_str())) {
+            return Err(anyhow!("unknown field {:?} in {}", key, what));
+        }
+    }
+    Ok(())
+}
+
+/// Parses a serialized block from an untrusted source, refusing inputs
+/// larger than `MAX_BLOCK_BYTES` before touching them.
+pub fn decode_block(bytes: &[u8]) -> Result<Block> {
+    decode_block_with(bytes, Decoding::Lenient)
+}
+
+pub fn decode_block_with(bytes: &[u8], decoding: Decoding) -> Result<Block> {
+    ensure!(
+        bytes.len() <= MAX_BLOCK_BYTES,
+        "serialized block is {} bytes, the limit is {}",
+        bytes.len(),
+        MAX_BLOCK_BYTES
+    );
+    if decoding == Decoding::Strict {
+        let value: serde_json::Value = serde_json::from_slice(bytes)?;
+        reject_unknown_fields(&value, BLOCK_FIELDS, "block")?;
+        reject_unknown_fields(&value["header"], HEADER_FIELDS, "header")?;
+        if let Some(txs) = value["txs"].as_array() {
+            for (i, tx) in txs.iter().enumerate() {
+                reject_unknown_fields(tx, TX_FIELDS, &format!("tx {}", i))?;
+            }
+        }
+    }
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+pub fn decode_tx(bytes: &[u8], decoding: Decoding) -> Result<Transaction> {
+    if decoding == Decoding::Strict {
+        let value: serde_json::Value = serde_json::from_slice(bytes)?;
+        reject_unknown_fields(&value, TX_FIELDS, "transaction")?;
+    }
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+fn is_zero(v: &u32) -> bool {
+    *v == 0
+}
+
+/// Parent hash recorded in the genesis header.
+pub const GENESIS_PARENT: &str = "0";
+
+// -----------------------------
+// Helpers
+// -----------------------------
+
+pub fn hash_block_header(h: &BlockHeader) -> String {
+    let serialized = serde_json::to_string(h).expect("serialize header");
+    let mut hasher = Sha256::new();
+    hasher.update(serialized.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub fn hash_block(block: &Block) -> String {
+    let serialized = serde_json::to_string(block).expect("serialize block");
+    let mut hasher = Sha256::new();
+    hasher.update(serialized.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Merkle root over pre-hashed leaves. An odd node is carried up to the
+/// next level unchanged; a pair is combined as
+/// `sha256(hex(left) || hex(right))`, over the hex text of each child,
+/// which is how the transaction tree has always combined its hashes. No
+/// leaves give an empty root and a single leaf is its own root.
+pub fn merkle_root_of(leaves: &[Vec<u8>]) -> Vec<u8> {
+    if leaves.is_empty() {
+        return Vec::new();
+    }
+    let mut level: Vec<Vec<u8>> = leaves.to_vec();
+    while level.len() > 1 {
+        let mut next = Vec::new();
+        for pair in level.chunks(2) {
+            if pair.len() == 1 {
+                next.push(pair[0].clone());
+            } else {
+                let mut h = Sha256::new();
+                h.update(hex::encode(&pair[0]).as_bytes());
+                h.update(hex::encode(&pair[1]).as_bytes());
+                next.push(h.finalize().to_vec());
+            }
+        }
+        level = next;
+    }
+    level.swap_remove(0)
+}
+
+impl Transaction {
+    /// The transaction id: `sha256` of its JSON encoding, which covers
+    /// every field (`from`, `to`, `amount`). Transactions carry no
+    /// signature or memo, so there is nothing to malleate and no separate
+    /// witness id; this is also the transaction's merkle leaf.
+    pub fn txid(&self) -> String {
+        hex::encode(self.txid_bytes())
+    }
+
+    fn txid_bytes(&self) -> Vec<u8> {
+        let s = serde_json::to_string(self).expect("serialize tx");
+        let mut h = Sha256::new();
+        h.update(s.as_bytes());
+        h.finalize().to_vec()
+    }
+}
+
+pub fn merkle_root(txs: &[Transaction]) -> String {
+    let leaves: Vec<Vec<u8>> = txs.iter().map(Transaction::txid_bytes).collect();
+    hex::encode(merkle_root_of(&leaves))
+}
+
+// -----------------------------
+// Validation
+// -----------------------------
+
+/// Which chain the node follows. Each network has its own parameters and
+/// data directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl Network {
+    /// Where this network's stores live. Mainnet keeps the original
+    /// location so existing chains are found.
+    pub fn data_dir(self) -> PathBuf {
+        match self {
+            Network::Mainnet => PathBuf::from("."),
+            Network::Testnet => PathBuf::from("./testnet"),
+            Network::Regtest => PathBuf::from("./regtest"),
+        }
+    }
+}
+
+impl std::str::FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            other => Err(anyhow!(
+                "unknown network {:?} (expected mainnet, testnet or regtest)",
+                other
+            )),
+        }
+    }
+}
+
+/// A 64-digit hex hash can start with at most 64 zero nibbles.
+pub const MAX_DIFFICULTY: u32 = 256;
+
+/// Consensus parameters shared by every node on a network.
+#[derive(Debug, Clone)]
+pub struct ChainParams {
+    /// Required difficulty for every block under `Retarget::Fixed`, and the
+    /// starting difficulty otherwise.
+    pub difficulty: u32,
+    /// Smallest transfer amount the mempool admits.
+    pub dust_threshold: u64,
+    /// Also reject blocks carrying dust, making the threshold consensus.
+    pub dust_is_consensus: bool,
+    /// Accept difficulties that require no work at all. Only regtest sets this.
+    pub allow_no_pow: bool,
+    /// Identifies the network in serialized data shared between nodes.
+    pub magic: [u8; 4],
+    /// Genesis header timestamp; fixing it makes the genesis hash fixed.
+    pub genesis_timestamp: i64,
+    /// Difficulty recorded in the genesis header. Genesis is not mined, so
+    /// this only feeds its hash and can be trivial while blocks stay hard.
+    pub genesis_difficulty: u32,
+    /// Bounds on any block's difficulty, including retarget results.
+    pub min_difficulty: u32,
+    pub max_difficulty: u32,
+    /// How the difficulty follows block times; `Fixed` ignores them.
+    pub retarget: Retarget,
+    /// Seconds between blocks the retarget rule aims for.
+    pub target_block_time: i64,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+impl ChainParams {
+    pub fn mainnet() -> Self {
+        Self {
+            // number of leading zero bits approximation; higher -> slower
+            difficulty: 12,
+            dust_threshold: 1,
+            dust_is_consensus: false,
+            allow_no_pow: false,
+            magic: *b"POWM",
+            genesis_timestamp: 1_704_067_200,
+            genesis_difficulty: 12,
+            min_difficulty: 4,
+            max_difficulty: MAX_DIFFICULTY,
+            retarget: Retarget::Fixed,
+            target_block_time: 10,
+        }
+    }
+
+    /// Public test network: cheaper blocks, same policy as mainnet.
+    pub fn testnet() -> Self {
+        Self {
+            difficulty: 8,
+            magic: *b"POWT",
+            genesis_timestamp: 1_704_153_600,
+            genesis_difficulty: 8,
+            ..Self::mainnet()
+        }
+    }
+
+    /// Local testing parameters: difficulty 0, so every hash is valid and
+    /// each block is mined on its first attempt.
+    pub fn regtest() -> Self {
+        Self {
+            difficulty: 0,
+            allow_no_pow: true,
+            min_difficulty: 0,
+            magic: *b"POWR",
+            genesis_timestamp: 1_704_240_000,
+            genesis_difficulty: 0,
+            ..Self::mainnet()
+        }
+    }
+
+    /// `difficulty` moved into `[min_difficulty, max_difficulty]`.
+    pub fn clamp_difficulty(&self, difficulty: u32) -> u32 {
+        difficulty.clamp(self.min_difficulty, self.max_difficulty)
+    }
+
+    pub fn for_network(network: Network) -> Self {
+        match network {
+            Network::Mainnet => Self::mainnet(),
+            Network::Testnet => Self::testnet(),
+            Network::Regtest => Self::regtest(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    HashMismatch {
+        stored: String,
+        computed: String,
+    },
+    MerkleMismatch {
+        header: String,
+        computed: String,
+    },
+    WrongDifficulty {
+        expected: u32,
+        found: u32,
+    },
+    InsufficientWork {
+        difficulty: u32,
+    },
+    DifficultyOutOfRange {
+        difficulty: u32,
+        min: u32,
+        max: u32,
+    },
+    NoPowNotAllowed {
+        difficulty: u32,
+    },
+    /// `index` is the transaction's position when a whole block was
+    /// checked.
+    DustOutput {
+        index: Option<usize>,
+        amount: u64,
+        threshold: u64,
+    },
+    TooManyTxs {
+        count: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::HashMismatch { stored, computed } => {
+                write!(
+                    f,
+                    "stored hash {} but header hashes to {}",
+                    stored, computed
+                )
+            }
+            ValidationError::MerkleMismatch { header, computed } => write!(
+                f,
+                "header merkle root {:?} but transactions give {:?}",
+                header, computed
+            ),
+            ValidationError::WrongDifficulty { expected, found } => {
+                write!(
+                    f,
+                    "difficulty {} but the network requires {}",
+                    found, expected
+                )
+            }
+            ValidationError::InsufficientWork { difficulty } => {
+                write!(f, "hash does not meet difficulty {}", difficulty)
+            }
+            ValidationError::DifficultyOutOfRange {
+                difficulty,
+                min,
+                max,
+            } => write!(
+                f,
+                "difficulty {} is outside the allowed range {}..={}",
+                difficulty, min, max
+            ),
+            ValidationError::NoPowNotAllowed { difficulty } => write!(
+                f,
+                "difficulty {} requires no work, which only regtest allows",
+                difficulty
+            ),
+            ValidationError::DustOutput {
+                index: Some(index),
+                amount,
+                threshold,
+            } => write!(
+                f,
+                "tx {} sends {}, below the dust threshold {}",
+                index, amount, threshold
+            ),
+            ValidationError::DustOutput {
+                index: None,
+                amount,
+                threshold,
+            } => write!(
+                f,
+                "amount {} is below the dust threshold {}",
+                amount, threshold
+            ),
+            ValidationError::TooManyTxs { count, max } => {
+                write!(f, "{} transactions, the limit is {}", count, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+pub fn check_hash(block: &Block) -> Result<(), ValidationError> {
+    let computed = hash_block_header(&block.header);
+    if computed != block.hash {
+        return Err(ValidationError::HashMismatch {
+            stored: block.hash.clone(),
+            computed,
+        });
+    }
+    Ok(())
+}
+
+pub fn check_merkle(block: &Block) -> Result<(), ValidationError> {
+    let computed = merkle_root(&block.txs);
+    if computed != block.header.merkle_root {
+        return Err(ValidationError::MerkleMismatch {
+            header: block.header.merkle_root.clone(),
+            computed,
+        });
+    }
+    Ok(())
+}
+
+/// Genesis is not mined, so it is exempt from the PoW check.
+pub fn check_pow(block: &Block) -> Result<(), ValidationError> {
+    if block.header.parent_hash != GENESIS_PARENT
+        && !meets_difficulty(&block.hash, block.header.difficulty)
+    {
+        return Err(ValidationError::InsufficientWork {
+            difficulty: block.header.difficulty,
+        });
+    }
+    Ok(())
+}
+
+/// Genesis only has to carry `genesis_difficulty`: it is not mined, so the
+/// work bounds do not apply to it.
+pub fn check_difficulty(block: &Block, params: &ChainParams) -> Result<(), ValidationError> {
+    if block.header.parent_hash == GENESIS_PARENT {
+        return check_retarget(block, params.genesis_difficulty);
+    }
+    if requires_no_work(block.header.difficulty) && !params.allow_no_pow {
+        return Err(ValidationError::NoPowNotAllowed {
+            difficulty: block.header.difficulty,
+        });
+    }
+    let difficulty = block.header.difficulty;
+    if difficulty < params.min_difficulty || difficulty > params.max_difficulty {
+        return Err(ValidationError::DifficultyOutOfRange {
+            difficulty,
+            min: params.min_difficulty,
+            max: params.max_difficulty,
+        });
+    }
+    // a retargeting network's expected difficulty depends on the chain,
+    // so only `check_retarget` can check it
+    if params.retarget == Retarget::Fixed && block.header.difficulty != params.difficulty {
+        return Err(ValidationError::WrongDifficulty {
+            expected: params.difficulty,
+            found: block.header.difficulty,
+        });
+    }
+    Ok(())
+}
+
+pub fn check_tx_count(block: &Block) -> Result<(), ValidationError> {
+    if block.txs.len() > MAX_BLOCK_TXS {
+        return Err(ValidationError::TooManyTxs {
+            count: block.txs.len(),
+            max: MAX_BLOCK_TXS,
+        });
+    }
+    Ok(())
+}
+
+/// Rejects dust transfers when the network makes the threshold consensus.
+pub fn check_dust(block: &Block, params: &ChainParams) -> Result<(), ValidationError> {
+    if !params.dust_is_consensus {
+        return Ok(());
+    }
+    for (index, tx) in block.txs.iter().enumerate() {
+        if tx.amount < params.dust_threshold {
+            return Err(ValidationError::DustOutput {
+                index: Some(index),
+                amount: tx.amount,
+                threshold: params.dust_threshold,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Context-free checks on a single block: the stored hash matches the
+/// header, the merkle root commits to the transactions, and the hash meets
+/// the header's difficulty.
+pub fn check_block(block: &Block) -> Result<()> {
+    check_hash(block)
+        .and_then(|()| check_merkle(block))
+        .and_then(|()| check_pow(block))
+        .with_context(|| format!("block {}", block.hash))
+}
+
+/// Runs every standalone check against `params`, keeping each outcome so
+/// callers can report more than the first failure.
+pub fn standalone_checks(
+    block: &Block,
+    params: &ChainParams,
+) -> Vec<(&'static str, Result<(), ValidationError>)> {
+    vec![
+        ("hash", check_hash(block)),
+        ("merkle", check_merkle(block)),
+        ("difficulty", check_difficulty(block, params)),
+        ("pow", check_pow(block)),
+        ("tx_count", check_tx_count(block)),
+        ("dust", check_dust(block, params)),
+    ]
+}
+
+/// Relay policy for a single transaction: the amount must reach the dust
+/// threshold whether or not the threshold is consensus.
+pub fn check_tx_dust(tx: &Transaction, params: &ChainParams) -> Result<(), ValidationError> {
+    if tx.amount < params.dust_threshold {
+        return Err(ValidationError::DustOutput {
+            index: None,
+            amount: tx.amount,
+            threshold: params.dust_threshold,
+        });
+    }
+    Ok(())
+}
+
+/// Context-free checks on a single transaction, in the shape of
+/// `standalone_checks`. Addresses are already checked by decoding, and
+/// transactions carry no signature, so only the amount is left.
+pub fn standalone_tx_checks(
+    tx: &Transaction,
+    params: &ChainParams,
+) -> Vec<(&'static str, Result<(), ValidationError>)> {
+    vec![("dust", check_tx_dust(tx, params))]
+}
+
+/// Standalone validation against `params`; returns the first failing check.
+pub fn validate_block(block: &Block, params: &ChainParams) -> Result<(), ValidationError> {
+    for (_, result) in standalone_checks(block, params) {
+        result?;
+    }
+    Ok(())
+}
+
+// -----------------------------
+// Difficulty retargeting
+// -----------------------------
+
+/// Rule deciding the difficulty of the next block from the headers that
+/// precede it. Difficulty is in bits, but work only changes on nibble
+/// boundaries (see `meets_difficulty`), so small adjustments accumulate
+/// before they take effect.
+pub trait DifficultyAdjuster {
+    /// How many headers, ending at the parent, `next_difficulty` needs for
+    /// the block at `next_height`.
+    fn window(&self, next_height: u64) -> usize;
+
+    /// Difficulty required of the block after `recent`, which is ordered
+    /// oldest first and ends at its parent. `recent` is shorter than
+    /// `window` near genesis. Genesis itself is not retargeted; it carries
+    /// `ChainParams::genesis_difficulty`.
+    fn next_difficulty(&self, recent: &[BlockHeader], params: &ChainParams) -> u32;
+}
+
+/// Every block uses `params.difficulty`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedDifficulty;
+
+impl DifficultyAdjuster for FixedDifficulty {
+    fn window(&self, _next_height: u64) -> usize {
+        0
+    }
+
+    fn next_difficulty(&self, _recent: &[BlockHeader], params: &ChainParams) -> u32 {
+        params.difficulty
+    }
+}
+
+/// Bitcoin-style retarget: difficulty holds for `interval` blocks, then
+/// moves by the ratio of expected to actual time across the epoch, by at
+/// most 2 bits (4x work) either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochRetarget {
+    pub interval: u64,
+}
+
+impl DifficultyAdjuster for EpochRetarget {
+    fn window(&self, next_height: u64) -> usize {
+        if next_height > 0 && next_height.is_multiple_of(self.interval) {
+            self.interval as usize
+        } else {
+            1
+        }
+    }
+
+    fn next_difficulty(&self, recent: &[BlockHeader], params: &ChainParams) -> u32 {
+        let (Some(first), Some(last)) = (recent.first(), recent.last()) else {
+            return params.difficulty;
+        };
+        if (recent.len() as u64) < self.interval {
+            return difficulty_after(last, params);
+        }
+        let expected = (recent.len() as i64 - 1)
+            .saturating_mul(params.target_block_time)
+            .max(1);
+        // timestamps are miner-chosen; past 4x either way the result is
+        // already capped, so bound the span before doing arithmetic on it
+        let actual = last
+            .timestamp
+            .saturating_sub(first.timestamp)
+            .clamp(1, expected.saturating_mul(4));
+        params.clamp_difficulty(shifted(difficulty_after(last, params), expected, actual, 2))
+    }
+}
+
+/// Linearly weighted moving average over the last `window` solve times,
+/// recent blocks weighing most, moving at most 1 bit per block. Reacts
+/// within a few blocks to hash rate changes instead of a whole epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lwma {
+    pub window: usize,
+}
+
+impl DifficultyAdjuster for Lwma {
+    fn window(&self, _next_height: u64) -> usize {
+        // n solve times need n + 1 timestamps
+        self.window + 1
+    }
+
+    fn next_difficulty(&self, recent: &[BlockHeader], params: &ChainParams) -> u32 {
+        let Some(last) = recent.last() else {
+            return params.difficulty;
+        };
+        if recent.len() < 2 {
+            return difficulty_after(last, params);
+        }
+        let target = params.target_block_time.max(1);
+        let mut weighted: i64 = 0;
+        let mut weights: i64 = 0;
+        for (i, pair) in recent.windows(2).enumerate() {
+            // bound each solve time so one bad timestamp cannot swing it
+            let solve = pair[1]
+                .timestamp
+                .saturating_sub(pair[0].timestamp)
+                .clamp(1, target.saturating_mul(6));
+            let weight = i as i64 + 1;
+            weighted = weighted.saturating_add(weight.saturating_mul(solve));
+            weights = weights.saturating_add(weight);
+        }
+        params.clamp_difficulty(shifted(
+            difficulty_after(last, params),
+            weights.saturating_mul(target),
+            weighted,
+            1,
+        ))
+    }
+}
+
+/// Difficulty a retarget starts from on top of `parent`. Genesis carries
+/// its own difficulty, so blocks after it start from `params.difficulty`.
+fn difficulty_after(parent: &BlockHeader, params: &ChainParams) -> u32 {
+    if parent.parent_hash == GENESIS_PARENT {
+        params.difficulty
+    } else {
+        parent.difficulty
+    }
+}
+
+/// `difficulty` raised by log2(expected / actual) bits, rounded toward
+/// zero and limited to `max_bits` either way.
+fn shifted(difficulty: u32, expected: i64, actual: i64, max_bits: u32) -> u32 {
+    // widened so the doubling below cannot overflow for any i64 input
+    let (expected, actual) = (expected.max(1) as i128, actual.max(1) as i128);
+    let mut bits = 0;
+    if actual < expected {
+        while bits < max_bits && actual << (bits + 1) <= expected {
+            bits += 1;
+        }
+        difficulty.saturating_add(bits)
+    } else {
+        while bits < max_bits && expected << (bits + 1) <= actual {
+            bits += 1;
+        }
+        difficulty.saturating_sub(bits)
+    }
+}
+
+/// Retargeting rule a network uses; `fixed` keeps every block at
+/// `ChainParams::difficulty`. Written `fixed`, `epoch[:INTERVAL]` or
+/// `lwma[:WINDOW]` on the command line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Retarget {
+    #[default]
+    Fixed,
+    Epoch(EpochRetarget),
+    Lwma(Lwma),
+}
+
+impl Retarget {
+    fn adjuster(&self) -> &dyn DifficultyAdjuster {
+        match self {
+            Retarget::Fixed => &FixedDifficulty,
+            Retarget::Epoch(epoch) => epoch,
+            Retarget::Lwma(lwma) => lwma,
+        }
+    }
+}
+
+impl DifficultyAdjuster for Retarget {
+    fn window(&self, next_height: u64) -> usize {
+        self.adjuster().window(next_height)
+    }
+
+    fn next_difficulty(&self, recent: &[BlockHeader], params: &ChainParams) -> u32 {
+        self.adjuster().next_difficulty(recent, params)
+    }
+}
+
+impl std::str::FromStr for Retarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, size) = match s.split_once(':') {
+            Some((name, size)) => (name, Some(size.parse::<u64>()?)),
+            None => (s, None),
+        };
+        let retarget = match name {
+            "fixed" if size.is_none() => Retarget::Fixed,
+            "epoch" => Retarget::Epoch(EpochRetarget {
+                interval: size.unwrap_or(16),
+            }),
+            "lwma" => Retarget::Lwma(Lwma {
+                window: size.unwrap_or(16) as usize,
+            }),
+            _ => {
+                return Err(anyhow!(
+                "unknown retarget rule {:?} (expected fixed, epoch[:INTERVAL] or lwma[:WINDOW])",
+                s
+            ))
+            }
+        };
+        ensure!(
+            size.is_none_or(|n| n >= 2),
+            "a retarget window needs at least two blocks"
+        );
+        Ok(retarget)
+    }
+}
+
+/// Contextual difficulty check: the header must carry exactly what the
+/// network's retarget rule computed for the block's position.
+pub fn check_retarget(block: &Block, expected: u32) -> Result<(), ValidationError> {
+    if block.header.difficulty != expected {
+        return Err(ValidationError::WrongDifficulty {
+            expected,
+            found: block.header.difficulty,
+        });
+    }
+    Ok(())
+}
+
+// -----------------------------
+// Storage wrapper (sled)
+// -----------------------------
+
+/// Version of the sled key layout, stored under `schema_version`.
+/// v2 added the `height/`, `blockheight/` and `work/` indexes; v3 added
+/// `children/` and backfilled `header/`.
+pub const SCHEMA_VERSION: u32 = 3;
+
+const LATEST_KEY: &[u8] = b"latest";
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+const MINER_STATS_PREFIX: &str = "miner_stats/";
+/// Best-chain index: `height/<u64 be>` -> block hash.
+const HEIGHT_PREFIX: &str = "height/";
+/// `blockheight/<hash>` -> u64 height, for every stored block.
+const BLOCK_HEIGHT_PREFIX: &str = "blockheight/";
+/// `work/<hash>` -> u128 cumulative work, for every stored block.
+const WORK_PREFIX: &str = "work/";
+/// `header/<hash>` -> header JSON, so header reads skip the body.
+/// Blocks stored before this index existed have no entry.
+const HEADER_PREFIX: &str = "header/";
+/// `children/<parent>/<child>` -> empty, for every stored block.
+const CHILDREN_PREFIX: &str = "children/";
+/// Final best-chain blocks: `checkpoint/<u64 be>` -> block hash.
+const CHECKPOINT_PREFIX: &str = "checkpoint/";
+
+fn height_key(height: u64) -> Vec<u8> {
+    u64_key(HEIGHT_PREFIX, height)
+}
+
+/// Big-endian so keys sort by height.
+fn u64_key(prefix: &str, n: u64) -> Vec<u8> {
+    let mut key = prefix.as_bytes().to_vec();
+    key.extend_from_slice(&n.to_be_bytes());
+    key
+}
+
+fn prefixed(prefix: &str, hash: &str) -> String {
+    format!("{}{}", prefix, hash)
+}
+
+fn child_key(parent: &str, child: &str) -> String {
+    format!("{}{}/{}", CHILDREN_PREFIX, parent, child)
+}
+
+/// Removes a block and every per-hash index entry for it.
+fn stage_block_removal(batch: &mut sled::Batch, header: &BlockHeader, hash: &str) {
+    batch.remove(hash.as_bytes());
+    for prefix in [BLOCK_HEIGHT_PREFIX, WORK_PREFIX, HEADER_PREFIX] {
+        batch.remove(prefixed(prefix, hash).as_bytes());
+    }
+    batch.remove(child_key(&header.parent_hash, hash).as_bytes());
+}
+
+fn decode_u64(bytes: &[u8]) -> Result<u64> {
+    let arr: [u8; 8] = bytes
+        .try_into()
+        .map_err(|_| anyhow!("expected 8 bytes, found {}", bytes.len()))?;
+    Ok(u64::from_be_bytes(arr))
+}
+
+fn decode_u128(bytes: &[u8]) -> Result<u128> {
+    let arr: [u8; 16] = bytes
+        .try_into()
+        .map_err(|_| anyhow!("expected 16 bytes, found {}", bytes.len()))?;
+    Ok(u128::from_be_bytes(arr))
+}
+
+/// Expected hashes needed to meet `difficulty`, matching the whole-nibble
+/// rounding in `meets_difficulty`.
+pub fn block_work(difficulty: u32) -> u128 {
+    let bits = (difficulty / 4 * 4).min(127);
+    1u128 << bits
+}
+
+/// What `accept_block` did with a block.
+#[derive(Debug, Clone)]
+pub enum AcceptOutcome {
+    /// The block was already stored.
+    AlreadyKnown,
+    /// The block became the new tip on top of the old one.
+    Extended,
+    /// The block was stored on a branch with less work than the tip.
+    SideBranch,
+    /// The block's branch overtook the tip. `disconnected` runs from the
+    /// old tip down to the fork point, `connected` from the fork point up
+    /// to the new tip.
+    Reorg {
+        disconnected: Vec<Block>,
+        connected: Vec<Block>,
+    },
+}
+
+/// Block persistence backend. The chain walks and startup checks are
+/// provided on top of the four required methods.
+pub trait Store {
+    /// Low-level write with no validation; nodes should go through
+    /// `accept_block`.
+    fn save_block(&self, block: &Block) -> Result<()>;
+
+    fn get_block(&self, hash: &str) -> Result<Option<Block>>;
+
+    /// Best-chain block at `height`.
+    fn block_at_height(&self, height: u64) -> Result<Option<Block>>;
+
+    /// Hash of the current tip, if any block has been saved.
+    fn latest_hash(&self) -> Result<Option<String>>;
+
+    fn schema_version(&self) -> Result<Option<u32>>;
+
+    /// Layout version this build reads and writes.
+    fn expected_schema_version(&self) -> u32;
+
+    /// Deletes every block above `height` so the best-chain block at
+    /// `height` becomes the tip. Returns the number of blocks removed.
+    fn rollback_to(&self, height: u64) -> Result<u64>;
+
+    /// Validates `block` and adds it to the store. The provided version
+    /// keeps a single chain and only accepts blocks extending the tip.
+    fn accept_block(&self, block: &Block, params: &ChainParams) -> Result<AcceptOutcome> {
+        if self.get_block(&block.hash)?.is_some() {
+            return Ok(AcceptOutcome::AlreadyKnown);
+        }
+        validate_block(block, params)?;
+        check_retarget(
+            block,
+            self.next_difficulty(&block.header.parent_hash, params)?,
+        )?;
+        let tip = self.latest_hash()?;
+        let expected_parent = tip.as_deref().unwrap_or(GENESIS_PARENT);
+        ensure!(
+            block.header.parent_hash == expected_parent,
+            "block {} does not extend tip {}",
+            block.hash,
+            expected_parent
+        );
+        self.save_block(block)?;
+        Ok(AcceptOutcome::Extended)
+    }
+
+    /// Difficulty `params.retarget` requires of a block on `parent_hash`,
+    /// or the genesis difficulty for a block with no parent.
+    /// The provided version walks the parent's chain back to genesis to
+    /// learn its height; stores that index heights override it.
+    fn next_difficulty(&self, parent_hash: &str, params: &ChainParams) -> Result<u32> {
+        if parent_hash == GENESIS_PARENT {
+            return Ok(params.genesis_difficulty);
+        }
+        let mut headers = Vec::new();
+        let mut hash = parent_hash.to_string();
+        while hash != GENESIS_PARENT {
+            let block = self
+                .get_block(&hash)?
+                .ok_or_else(|| anyhow!("missing ancestor {}", hash))?;
+            hash = block.header.parent_hash.clone();
+            headers.push(block.header);
+        }
+        let window = params.retarget.window(headers.len() as u64);
+        headers.truncate(window);
+        headers.reverse();
+        Ok(params.retarget.next_difficulty(&headers, params))
+    }
+
+    fn get_latest(&self) -> Result<Option<Block>> {
+        match self.latest_hash()? {
+            Some(hash) => self.get_block(&hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Walks the chain backward from the tip to genesis by parent hash.
+    fn iter_chain(&self) -> Result<ChainIter<'_, Self>>
+    where
+        Self: Sized,
+    {
+        ChainIter::new(self)
+    }
+
+    /// Runs `check_block` on every block from the tip back to genesis and
+    /// returns the verified tip height (genesis is height 0).
+    fn verify_chain(&self) -> Result<u64> {
+        let mut count: u64 = 0;
+        for block in ChainIter::new(self)? {
+            check_block(&block?)?;
+            count += 1;
+        }
+        ensure!(count > 0, "chain is empty");
+        Ok(count - 1)
+    }
+
+    /// Returns the genesis block reached by walking back from the tip.
+    fn genesis(&self) -> Result<Option<Block>> {
+        let mut root = None;
+        for block in ChainIter::new(self)? {
+            root = Some(block?);
+        }
+        Ok(root)
+    }
+
+    /// Statistics over the intervals between the last `window` best-chain
+    /// blocks (so `window - 1` intervals). Variance is the population
+    /// variance of those intervals.
+    fn block_interval_stats(&self, window: usize) -> Result<IntervalStats> {
+        let mut timestamps = Vec::with_capacity(window);
+        for block in ChainIter::new(self)?.take(window) {
+            timestamps.push(block?.header.timestamp);
+        }
+        // newest first; each interval is child minus parent
+        let intervals: Vec<i64> = timestamps.windows(2).map(|w| w[0] - w[1]).collect();
+        ensure!(
+            !intervals.is_empty(),
+            "need at least two blocks for interval stats"
+        );
+        let n = intervals.len() as f64;
+        let mean = intervals.iter().sum::<i64>() as f64 / n;
+        let variance = intervals
+            .iter()
+            .map(|&i| (i as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        Ok(IntervalStats {
+            count: intervals.len(),
+            mean,
+            min: *intervals.iter().min().unwrap(),
+            max: *intervals.iter().max().unwrap(),
+            variance,
+            std_dev: variance.sqrt(),
+        })
+    }
+
+    /// For each version bit, how many of the last `window` best-chain
+    /// blocks set it.
+    fn version_bit_statistics(&self, window: usize) -> Result<[u64; 32]> {
+        let mut counts = [0u64; 32];
+        for block in ChainIter::new(self)?.take(window) {
+            let version = block?.header.version;
+            for (bit, count) in counts.iter_mut().enumerate() {
+                if version & (1 << bit) != 0 {
+                    *count += 1;
+                }
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Checks the store's indexes against its blocks without modifying
+    /// anything. The provided version only checks that `latest` names a
+    /// stored block; stores that keep derived indexes override it.
+    fn verify_indexes(&self) -> Result<IndexReport> {
+        let mut discrepancies = Vec::new();
+        let mut blocks_checked = 0;
+        if let Some(hash) = self.latest_hash()? {
+            match self.get_block(&hash)? {
+                Some(block) if block.hash == hash => blocks_checked = 1,
+                _ => discrepancies.push(("latest", format!("names missing block {}", hash))),
+            }
+        }
+        Ok(IndexReport {
+            blocks_checked,
+            discrepancies,
+        })
+    }
+
+    /// Runs the startup self-checks. Individual check failures are recorded
+    /// in the report rather than returned as errors.
+    fn startup_report(&self) -> Result<StartupReport> {
+        let mut errors = Vec::new();
+
+        let schema_version = self.schema_version()?;
+        let expected_schema_version = self.expected_schema_version();
+        if schema_version != Some(expected_schema_version) {
+            errors.push(format!(
+                "schema version {:?}, expected {}",
+                schema_version, expected_schema_version
+            ));
+        }
+
+        let mut indexes_consistent = true;
+        if self.latest_hash()?.is_none() {
+            errors.push("no latest block recorded".to_string());
+            indexes_consistent = false;
+        }
+        match self.verify_indexes() {
+            Ok(report) => {
+                for (index, problem) in &report.discrepancies {
+                    errors.push(format!("{} index: {}", index, problem));
+                }
+                indexes_consistent &= report.is_consistent();
+            }
+            Err(e) => {
+                errors.push(format!("index check failed: {:#}", e));
+                indexes_consistent = false;
+            }
+        }
+
+        let mut genesis_ok = false;
+        match self.genesis() {
+            Ok(Some(genesis)) => match check_block(&genesis) {
+                Ok(()) => genesis_ok = true,
+                Err(e) => errors.push(format!("genesis invalid: {:#}", e)),
+            },
+            Ok(None) => errors.push("no genesis block".to_string()),
+            Err(e) => errors.push(format!("genesis not reachable from tip: {:#}", e)),
+        }
+
+        let verified_height = match self.verify_chain() {
+            Ok(height) => Some(height),
+            Err(e) => {
+                errors.push(format!("chain verification failed: {:#}", e));
+                None
+            }
+        };
+
+        Ok(StartupReport {
+            genesis_ok,
+            verified_height,
+            indexes_consistent,
+            schema_version,
+            expected_schema_version,
+            errors,
+        })
+    }
+}
+
+/// Sled-backed store with fork choice and the derived indexes.
+///
+/// Locking: every change to the tip or block set (`connect_block`,
+/// `rollback_to`, `prune_orphans`) runs with `tip_lock` held, from its
+/// first index read to the audit log write, so concurrent acceptance is
+/// serialized and a reorg cannot interleave with another. The audit
+/// mutex is only taken under `tip_lock`. Queries take no lock; each sled
+/// batch is atomic, so a reader sees the chain either before or after a
+/// change.
+pub struct ChainDB {
+    db: Db,
+    audit: Option<Mutex<AuditLog>>,
+    /// Record a checkpoint at every best-chain height divisible by this.
+    auto_checkpoint_interval: Option<u64>,
+    tip_lock: Mutex<()>,
+}
+
+impl ChainDB {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let db = sled::open(path)?;
+        let chain_db = Self {
+            db,
+            audit: None,
+            auto_checkpoint_interval: None,
+            tip_lock: Mutex::new(()),
+        };
+        match chain_db.schema_version()? {
+            // databases written before the version key existed share the
+            // v1 layout
+            Some(1) | None => {
+                chain_db.migrate_v1()?;
+                chain_db.migrate_v2()?;
+            }
+            Some(2) => chain_db.migrate_v2()?,
+            // unknown versions are left alone for the startup report to flag
+            _ => {}
+        }
+        Ok(chain_db)
+    }
+
+    /// Appends every best-chain change to `log` from now on.
+    pub fn with_audit_log(mut self, log: AuditLog) -> Self {
+        self.audit = Some(Mutex::new(log));
+        self
+    }
+
+    /// Checkpoints the best chain every `interval` blocks from now on.
+    /// Blocks at or below the latest checkpoint are final: a reorg that
+    /// forks below it is rejected.
+    pub fn with_auto_checkpoints(mut self, interval: u64) -> Self {
+        self.auto_checkpoint_interval = Some(interval).filter(|&i| i > 0);
+        self
+    }
+
+    /// Recorded checkpoints as `(height, hash)`, lowest first.
+    pub fn checkpoints(&self) -> Result<Vec<(u64, String)>> {
+        self.db
+            .scan_prefix(CHECKPOINT_PREFIX.as_bytes())
+            .map(|item| {
+                let (k, v) = item?;
+                let height = decode_u64(&k[CHECKPOINT_PREFIX.len()..])?;
+                Ok((height, String::from_utf8(v.to_vec())?))
+            })
+            .collect()
+    }
+
+    pub fn latest_checkpoint(&self) -> Result<Option<(u64, String)>> {
+        match self
+            .db
+            .scan_prefix(CHECKPOINT_PREFIX.as_bytes())
+            .next_back()
+        {
+            Some(item) => {
+                let (k, v) = item?;
+                let height = decode_u64(&k[CHECKPOINT_PREFIX.len()..])?;
+                Ok(Some((height, String::from_utf8(v.to_vec())?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn stage_checkpoint(&self, batch: &mut sled::Batch, height: u64, hash: &str) {
+        if let Some(interval) = self.auto_checkpoint_interval {
+            if height > 0 && height.is_multiple_of(interval) {
+                batch.insert(u64_key(CHECKPOINT_PREFIX, height), hash.as_bytes());
+            }
+        }
+    }
+
+    /// v1 stored a single linear chain with no height or work indexes;
+    /// rebuild them by walking back from the tip.
+    fn migrate_v1(&self) -> Result<()> {
+        let mut chain = Vec::new();
+        for block in ChainIter::new(self)? {
+            chain.push(block?);
+        }
+        chain.reverse();
+        let mut batch = sled::Batch::default();
+        let mut work: u128 = 0;
+        for (height, block) in chain.iter().enumerate() {
+            work += block_work(block.header.difficulty);
+            batch.insert(height_key(height as u64), block.hash.as_bytes());
+            batch.insert(
+                prefixed(BLOCK_HEIGHT_PREFIX, &block.hash).as_bytes(),
+                &(height as u64).to_be_bytes(),
+            );
+            batch.insert(
+                prefixed(WORK_PREFIX, &block.hash).as_bytes(),
+                &work.to_be_bytes(),
+            );
+        }
+        batch.insert(SCHEMA_VERSION_KEY, &2u32.to_be_bytes());
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// v2 had no child links and only blocks saved recently had header
+    /// entries; build both from the stored blocks.
+    fn migrate_v2(&self) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for item in self.db.scan_prefix(BLOCK_HEIGHT_PREFIX.as_bytes()) {
+            let (k, _) = item?;
+            let hash = std::str::from_utf8(&k[BLOCK_HEIGHT_PREFIX.len()..])?;
+            let block = self
+                .get_block(hash)?
+                .ok_or_else(|| anyhow!("height index names missing block {}", hash))?;
+            batch.insert(
+                prefixed(HEADER_PREFIX, hash).as_bytes(),
+                serde_json::to_vec(&block.header)?,
+            );
+            batch.insert(child_key(&block.header.parent_hash, hash).as_bytes(), &[]);
+        }
+        batch.insert(SCHEMA_VERSION_KEY, &3u32.to_be_bytes());
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    pub fn miner_block_count(&self, miner: &Address) -> Result<u64> {
+        let key = prefixed(MINER_STATS_PREFIX, miner.as_str());
+        match self.db.get(key.as_bytes())? {
+            Some(v) => decode_u64(&v),
+            None => Ok(0),
+        }
+    }
+
+    /// Header of `hash`, read without loading the transactions when the
+    /// header index has it.
+    pub fn header(&self, hash: &str) -> Result<Option<BlockHeader>> {
+        match self.db.get(prefixed(HEADER_PREFIX, hash).as_bytes())? {
+            Some(v) => Ok(Some(serde_json::from_slice(&v)?)),
+            None => Ok(self.get_block(hash)?.map(|b| b.header)),
+        }
+    }
+
+    /// Re-runs the context-free checks on one stored block: its header
+    /// hashes to the key it is stored under and to its own hash field, the
+    /// merkle root commits to the transactions, and the hash meets the
+    /// header's difficulty. The outer error covers a missing or unreadable
+    /// block; the inner one is the first check the stored block fails.
+    pub fn reverify_block(&self, hash: &str) -> Result<Result<(), ValidationError>> {
+        let block = self
+            .get_block(hash)?
+            .ok_or_else(|| anyhow!("no block {} in the store", hash))?;
+        let keyed = Block {
+            hash: hash.to_string(),
+            ..block.clone()
+        };
+        Ok(check_hash(&keyed)
+            .and_then(|()| check_hash(&block))
+            .and_then(|()| check_merkle(&block))
+            .and_then(|()| check_pow(&block)))
+    }
+
+    /// Height, work, best-chain status and size of `hash` in one call.
+    pub fn get_block_meta(&self, hash: &str) -> Result<Option<BlockMeta>> {
+        let Some(raw) = self.db.get(hash.as_bytes())? else {
+            return Ok(None);
+        };
+        let block: Block = serde_json::from_slice(&raw)?;
+        let height = self
+            .height_of(hash)?
+            .ok_or_else(|| anyhow!("missing height index for {}", hash))?;
+        let work = self
+            .stored_work(hash)?
+            .ok_or_else(|| anyhow!("missing work index for {}", hash))?;
+        let on_best_chain = self.on_best_chain(hash)?;
+        let confirmations = match (on_best_chain, self.tip_height()?) {
+            (true, Some(tip)) => tip - height + 1,
+            _ => 0,
+        };
+        Ok(Some(BlockMeta {
+            height,
+            work,
+            on_best_chain,
+            confirmations,
+            tx_count: block.txs.len(),
+            size: raw.len(),
+            timestamp: block.header.timestamp,
+            difficulty: block.header.difficulty,
+        }))
+    }
+
+    /// Hashes of every stored block whose parent is `hash`.
+    pub fn children(&self, hash: &str) -> Result<Vec<String>> {
+        let prefix = child_key(hash, "");
+        self.db
+            .scan_prefix(prefix.as_bytes())
+            .map(|item| Ok(std::str::from_utf8(&item?.0[prefix.len()..])?.to_string()))
+            .collect()
+    }
+
+    /// Walks forward from `hash` through its descendants, starting with
+    /// `hash` itself. From a best-chain block it follows the best chain to
+    /// the tip; from a side branch it follows the child with the most
+    /// work.
+    pub fn walk_from(&self, hash: &str) -> ForwardIter<'_> {
+        ForwardIter {
+            db: self,
+            next: Some(hash.to_string()),
+        }
+    }
+
+    fn best_child(&self, hash: &str) -> Result<Option<String>> {
+        let mut best: Option<(u128, String)> = None;
+        for child in self.children(hash)? {
+            if self.on_best_chain(&child)? {
+                return Ok(Some(child));
+            }
+            let work = self
+                .stored_work(&child)?
+                .ok_or_else(|| anyhow!("missing work index for {}", child))?;
+            if best.as_ref().is_none_or(|(w, _)| work > *w) {
+                best = Some((work, child));
+            }
+        }
+        Ok(best.map(|(_, child)| child))
+    }
+
+    /// Writes every best-chain header, genesis first, for
+    /// `import_headers`. Returns the number written.
+    pub fn export_headers(&self, mut w: impl Write) -> Result<u64> {
+        w.write_all(HEADERS_MAGIC)?;
+        w.write_all(&[HEADERS_FORMAT])?;
+        let mut height = 0;
+        while let Some(hash) = self.hash_at_height(height)? {
+            let header = self
+                .header(&hash)?
+                .ok_or_else(|| anyhow!("height index names missing block {}", hash))?;
+            write_header(&mut w, &header)?;
+            height += 1;
+        }
+        w.flush()?;
+        Ok(height)
+    }
+
+    pub fn tip_header(&self) -> Result<Option<BlockHeader>> {
+        match self.latest_hash()? {
+            Some(hash) => self.header(&hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Best-chain blocks per miner, excluding genesis. Blocks saved before
+    /// the `miner_stats/` index existed are not counted.
+    pub fn miner_block_counts(&self) -> Result<Vec<(Address, u64)>> {
+        let mut counts = Vec::new();
+        for item in self.db.scan_prefix(MINER_STATS_PREFIX.as_bytes()) {
+            let (k, v) = item?;
+            let miner = Address::parse(std::str::from_utf8(&k[MINER_STATS_PREFIX.len()..])?)?;
+            counts.push((miner, decode_u64(&v)?));
+        }
+        Ok(counts)
+    }
+
+    /// Approximate bytes (keys plus values) held by each keyspace, sorted
+    /// by keyspace name. Full blocks are reported as `blocks`, the
+    /// `latest` and `schema_version` keys as `meta`. sled's own overhead
+    /// is not included, so the sum is below `size_on_disk`.
+    pub fn size_estimates(&self) -> Result<Vec<(&'static str, u64)>> {
+        const KEYSPACES: [&str; 8] = [
+            MINER_STATS_PREFIX,
+            HEIGHT_PREFIX,
+            BLOCK_HEIGHT_PREFIX,
+            WORK_PREFIX,
+            HEADER_PREFIX,
+            CHILDREN_PREFIX,
+            CHECKPOINT_PREFIX,
+            "",
+        ];
+        let mut sizes = [0u64; KEYSPACES.len()];
+        let mut meta = 0;
+        for item in self.db.iter() {
+            let (k, v) = item?;
+            let len = (k.len() + v.len()) as u64;
+            if k.as_ref() == LATEST_KEY || k.as_ref() == SCHEMA_VERSION_KEY {
+                meta += len;
+                continue;
+            }
+            // block keys are bare hashes, so the empty prefix is last
+            let i = KEYSPACES
+                .iter()
+                .position(|p| k.starts_with(p.as_bytes()))
+                .expect("empty prefix matches");
+            sizes[i] += len;
+        }
+        let mut out: Vec<(&'static str, u64)> = KEYSPACES
+            .iter()
+            .zip(sizes)
+            .map(|(p, n)| (p.strip_suffix('/').unwrap_or("blocks"), n))
+            .collect();
+        out.push(("meta", meta));
+        out.sort();
+        Ok(out)
+    }
+
+    /// Bytes sled uses on disk, including its own overhead.
+    pub fn size_on_disk(&self) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+
+    /// Hash of the best-chain block at `height`.
+    pub fn hash_at_height(&self, height: u64) -> Result<Option<String>> {
+        match self.db.get(height_key(height))? {
+            Some(v) => Ok(Some(String::from_utf8(v.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Height of any stored block, read from the `blockheight/` index
+    /// without walking the chain. Side-chain blocks keep their height
+    /// when a reorg leaves them off the best chain, so a `Some` here does
+    /// not mean the block is on it; compare with `hash_at_height` for
+    /// that. Blocks removed by `rollback_to` give `None`.
+    pub fn height_of(&self, hash: &str) -> Result<Option<u64>> {
+        match self
+            .db
+            .get(prefixed(BLOCK_HEIGHT_PREFIX, hash).as_bytes())?
+        {
+            Some(v) => Ok(Some(decode_u64(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stored_work(&self, hash: &str) -> Result<Option<u128>> {
+        match self.db.get(prefixed(WORK_PREFIX, hash).as_bytes())? {
+            Some(v) => Ok(Some(decode_u128(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Height of the best-chain tip.
+    pub fn tip_height(&self) -> Result<Option<u64>> {
+        match self.latest_hash()? {
+            Some(hash) => self.height_of(&hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Net amount each address sent or received in the best-chain blocks
+    /// at heights `(a, b]`, sorted by address. Addresses can appear with a
+    /// zero net change.
+    pub fn balance_diff_between(&self, a: u64, b: u64) -> Result<Vec<(Address, i128)>> {
+        ensure!(a <= b, "range start {} is after its end {}", a, b);
+        let mut deltas: HashMap<Address, i128> = HashMap::new();
+        for height in a + 1..=b {
+            let block = self
+                .block_at_height(height)?
+                .ok_or_else(|| anyhow!("no best-chain block at height {}", height))?;
+            for tx in &block.txs {
+                let amount = i128::from(tx.amount);
+                for (address, change) in [(&tx.from, -amount), (&tx.to, amount)] {
+                    let delta = deltas.entry(address.clone()).or_default();
+                    *delta = delta
+                        .checked_add(change)
+                        .ok_or_else(|| anyhow!("balance delta of {} overflows", address))?;
+                }
+            }
+        }
+        let mut deltas: Vec<(Address, i128)> = deltas.into_iter().collect();
+        deltas.sort();
+        Ok(deltas)
+    }
+
+    /// Up to `n` ancestor hashes of `hash`, parent first. Walks the header
+    /// index only.
+    pub fn ancestors(&self, hash: &str, n: usize) -> Result<Vec<String>> {
+        let mut cursor = self
+            .header(hash)?
+            .ok_or_else(|| anyhow!("unknown block {}", hash))?;
+        let mut out = Vec::new();
+        while out.len() < n && cursor.parent_hash != GENESIS_PARENT {
+            let parent = cursor.parent_hash.clone();
+            cursor = self
+                .header(&parent)?
+                .ok_or_else(|| anyhow!("missing ancestor {}", parent))?;
+            out.push(parent);
+        }
+        Ok(out)
+    }
+
+    /// Whether `maybe_ancestor` is `descendant` or one of its ancestors.
+    /// The walk is bounded by the height difference between the two.
+    pub fn is_ancestor(&self, maybe_ancestor: &str, descendant: &str) -> Result<bool> {
+        let a = self
+            .height_of(maybe_ancestor)?
+            .ok_or_else(|| anyhow!("unknown block {}", maybe_ancestor))?;
+        let d = self
+            .height_of(descendant)?
+            .ok_or_else(|| anyhow!("unknown block {}", descendant))?;
+        if a > d {
+            return Ok(false);
+        }
+        if self.on_best_chain(maybe_ancestor)? && self.on_best_chain(descendant)? {
+            return Ok(true);
+        }
+        let steps = (d - a) as usize;
+        let found = match steps {
+            0 => descendant.to_string(),
+            _ => self
+                .ancestors(descendant, steps)?
+                .pop()
+                .ok_or_else(|| anyhow!("{} has no ancestor at height {}", descendant, a))?,
+        };
+        Ok(found == maybe_ancestor)
+    }
+
+    fn on_best_chain(&self, hash: &str) -> Result<bool> {
+        match self.height_of(hash)? {
+            Some(h) => Ok(self.hash_at_height(h)?.as_deref() == Some(hash)),
+            None => Ok(false),
+        }
+    }
+
+    /// Deletes side-branch blocks below `older_than_height`, along with any
+    /// side-branch descendants left without a parent, and returns how many
+    /// were removed. Best-chain blocks are never touched.
+    pub fn prune_orphans(&self, older_than_height: u64) -> Result<u64> {
+        let _tip = self.tip_lock.lock().unwrap();
+        let mut orphans = Vec::new();
+        for item in self.db.scan_prefix(BLOCK_HEIGHT_PREFIX.as_bytes()) {
+            let (k, v) = item?;
+            let hash = std::str::from_utf8(&k[BLOCK_HEIGHT_PREFIX.len()..])?.to_string();
+            let height = decode_u64(&v)?;
+            if self.hash_at_height(height)?.as_deref() != Some(hash.as_str()) {
+                orphans.push((height, hash));
+            }
+        }
+        // parents sort before children, so detached descendants are seen
+        // after their pruned parent
+        orphans.sort();
+
+        let mut removed = HashSet::new();
+        let mut batch = sled::Batch::default();
+        for (height, hash) in orphans {
+            let Some(block) = self.get_block(&hash)? else {
+                continue;
+            };
+            if height < older_than_height || removed.contains(&block.header.parent_hash) {
+                stage_block_removal(&mut batch, &block.header, &hash);
+                removed.insert(hash);
+            }
+        }
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(removed.len() as u64)
+    }
+
+    /// Rebuilds every derived index, including `miner_stats/`, from the
+    /// stored blocks in one batch, keeping the current tip. Blocks that no
+    /// longer connect to genesis are deleted, since they could never be
+    /// indexed or extended. Returns the number of blocks reindexed.
+    pub fn reindex(&self) -> Result<u64> {
+        let _tip = self.tip_lock.lock().unwrap();
+        let derived = self.derive_indexes()?;
+        let mut batch = sled::Batch::default();
+        for prefix in [
+            HEIGHT_PREFIX,
+            BLOCK_HEIGHT_PREFIX,
+            WORK_PREFIX,
+            HEADER_PREFIX,
+            CHILDREN_PREFIX,
+            MINER_STATS_PREFIX,
+        ] {
+            for item in self.db.scan_prefix(prefix.as_bytes()) {
+                batch.remove(item?.0);
+            }
+        }
+        for hash in &derived.detached {
+            batch.remove(hash.as_bytes());
+        }
+        for (hash, header) in &derived.headers {
+            let (Some(height), Some(work)) = (derived.heights.get(hash), derived.work.get(hash))
+            else {
+                continue;
+            };
+            batch.insert(
+                prefixed(BLOCK_HEIGHT_PREFIX, hash).as_bytes(),
+                &height.to_be_bytes(),
+            );
+            batch.insert(prefixed(WORK_PREFIX, hash).as_bytes(), &work.to_be_bytes());
+            batch.insert(
+                prefixed(HEADER_PREFIX, hash).as_bytes(),
+                serde_json::to_vec(header)?,
+            );
+            batch.insert(child_key(&header.parent_hash, hash).as_bytes(), &[]);
+        }
+        let mut miner_counts: HashMap<&Address, u64> = HashMap::new();
+        for (height, hash) in derived.best.iter().enumerate() {
+            batch.insert(height_key(height as u64), hash.as_bytes());
+            if height > 0 {
+                *miner_counts
+                    .entry(&derived.headers[hash].miner)
+                    .or_default() += 1;
+            }
+        }
+        for (miner, count) in miner_counts {
+            batch.insert(
+                prefixed(MINER_STATS_PREFIX, miner.as_str()).as_bytes(),
+                &count.to_be_bytes(),
+            );
+        }
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(derived.heights.len() as u64)
+    }
+
+    /// What every derived index should hold, computed only from the block
+    /// entries and `latest`.
+    fn derive_indexes(&self) -> Result<DerivedIndexes> {
+        let mut headers = HashMap::new();
+        for item in self.db.iter() {
+            let (k, v) = item?;
+            // every index key has a `/` prefix; what is left is blocks
+            if k.contains(&b'/') || *k == *LATEST_KEY || *k == *SCHEMA_VERSION_KEY {
+                continue;
+            }
+            let block: Block = serde_json::from_slice(&v)?;
+            headers.insert(String::from_utf8(k.to_vec())?, block.header);
+        }
+
+        let mut heights: HashMap<String, u64> = HashMap::new();
+        let mut work: HashMap<String, u128> = HashMap::new();
+        let mut detached = Vec::new();
+        for start in headers.keys() {
+            // climb to the first block with a known height (or past
+            // genesis), then fill in the path back down
+            let mut path = Vec::new();
+            let mut cursor = start.as_str();
+            let base = loop {
+                if let Some(h) = heights.get(cursor) {
+                    break Some((h + 1, work[cursor]));
+                }
+                let Some(header) = headers.get(cursor) else {
+                    break None;
+                };
+                path.push(cursor);
+                if header.parent_hash == GENESIS_PARENT {
+                    break Some((0, 0));
+                }
+                cursor = &header.parent_hash;
+            };
+            let Some((mut height, mut total)) = base else {
+                detached.extend(path.iter().map(|h| h.to_string()));
+                continue;
+            };
+            for hash in path.into_iter().rev() {
+                total += block_work(headers[hash].difficulty);
+                heights.insert(hash.to_string(), height);
+                work.insert(hash.to_string(), total);
+                height += 1;
+            }
+        }
+        detached.sort();
+        detached.dedup();
+        // a detached path may have been filled in through another start
+        detached.retain(|h| !heights.contains_key(h));
+
+        let mut best = Vec::new();
+        let mut cursor = self.latest_hash()?;
+        while let Some(hash) = cursor {
+            let Some(header) = headers.get(&hash) else {
+                break;
+            };
+            cursor = (header.parent_hash != GENESIS_PARENT).then(|| header.parent_hash.clone());
+            best.push(hash);
+        }
+        best.reverse();
+        Ok(DerivedIndexes {
+            headers,
+            heights,
+            work,
+            best,
+            detached,
+        })
+    }
+
+    /// Stores `block` and runs fork choice (most cumulative work wins),
+    /// updating every index in one batch. The parent must already be
+    /// stored. No validation happens here.
+    fn connect_block(&self, block: &Block) -> Result<AcceptOutcome> {
+        let _tip = self.tip_lock.lock().unwrap();
+        if self.db.get(block.hash.as_bytes())?.is_some() {
+            return Ok(AcceptOutcome::AlreadyKnown);
+        }
+        let tip = self.latest_hash()?;
+
+        let (height, work) = if block.header.parent_hash == GENESIS_PARENT {
+            ensure!(
+                tip.is_none(),
+                "genesis {} offered to a non-empty chain",
+                block.hash
+            );
+            (0, block_work(block.header.difficulty))
+        } else {
+            let parent = &block.header.parent_hash;
+            let parent_height = self
+                .height_of(parent)?
+                .ok_or_else(|| anyhow!("block {} has unknown parent {}", block.hash, parent))?;
+            let parent_work = self
+                .stored_work(parent)?
+                .ok_or_else(|| anyhow!("missing work index for {}", parent))?;
+            (
+                parent_height + 1,
+                parent_work + block_work(block.header.difficulty),
+            )
+        };
+
+        let mut batch = sled::Batch::default();
+        batch.insert(block.hash.as_bytes(), serde_json::to_vec(block)?);
+        batch.insert(
+            prefixed(BLOCK_HEIGHT_PREFIX, &block.hash).as_bytes(),
+            &height.to_be_bytes(),
+        );
+        batch.insert(
+            prefixed(WORK_PREFIX, &block.hash).as_bytes(),
+            &work.to_be_bytes(),
+        );
+        batch.insert(
+            prefixed(HEADER_PREFIX, &block.hash).as_bytes(),
+            serde_json::to_vec(&block.header)?,
+        );
+        batch.insert(
+            child_key(&block.header.parent_hash, &block.hash).as_bytes(),
+            &[],
+        );
+
+        let tip_work = match &tip {
+            Some(hash) => self
+                .stored_work(hash)?
+                .ok_or_else(|| anyhow!("missing work index for tip {}", hash))?,
+            None => 0,
+        };
+        let outcome = if tip.is_none() || tip.as_deref() == Some(block.header.parent_hash.as_str())
+        {
+            AcceptOutcome::Extended
+        } else if work > tip_work {
+            let (disconnected, connected) = self.fork_branches(block)?;
+            if let Some((checkpoint, hash)) = self.latest_checkpoint()? {
+                let fork_height = height - connected.len() as u64;
+                ensure!(
+                    fork_height >= checkpoint,
+                    "block {} would reorg from height {}, below checkpoint {} at {}",
+                    block.hash,
+                    fork_height,
+                    hash,
+                    checkpoint
+                );
+            }
+            AcceptOutcome::Reorg {
+                disconnected,
+                connected,
+            }
+        } else {
+            AcceptOutcome::SideBranch
+        };
+
+        // best-chain indexes
+        let mut miner_deltas: HashMap<&Address, i64> = HashMap::new();
+        match &outcome {
+            AcceptOutcome::Extended => {
+                batch.insert(height_key(height), block.hash.as_bytes());
+                batch.insert(LATEST_KEY, block.hash.as_bytes());
+                self.stage_checkpoint(&mut batch, height, &block.hash);
+                if height > 0 {
+                    *miner_deltas.entry(&block.header.miner).or_default() += 1;
+                }
+            }
+            AcceptOutcome::Reorg {
+                disconnected,
+                connected,
+            } => {
+                let old_tip_height = self.tip_height()?.unwrap_or(0);
+                for h in height + 1..=old_tip_height {
+                    batch.remove(height_key(h));
+                }
+                for b in disconnected {
+                    *miner_deltas.entry(&b.header.miner).or_default() -= 1;
+                }
+                let first = height + 1 - connected.len() as u64;
+                for (i, b) in connected.iter().enumerate() {
+                    batch.insert(height_key(first + i as u64), b.hash.as_bytes());
+                    self.stage_checkpoint(&mut batch, first + i as u64, &b.hash);
+                    *miner_deltas.entry(&b.header.miner).or_default() += 1;
+                }
+                batch.insert(LATEST_KEY, block.hash.as_bytes());
+            }
+            AcceptOutcome::SideBranch | AcceptOutcome::AlreadyKnown => {}
+        }
+        self.stage_miner_deltas(&mut batch, miner_deltas)?;
+
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+
+        if let Some(audit) = &self.audit {
+            let mut log = audit.lock().unwrap();
+            match &outcome {
+                AcceptOutcome::Extended => log.record_block(height, block),
+                AcceptOutcome::Reorg {
+                    disconnected,
+                    connected,
+                } => {
+                    let fork_height = height - connected.len() as u64;
+                    log.record_reorg(fork_height, disconnected).and_then(|()| {
+                        connected
+                            .iter()
+                            .enumerate()
+                            .try_for_each(|(i, b)| log.record_block(fork_height + 1 + i as u64, b))
+                    })
+                }
+                AcceptOutcome::SideBranch | AcceptOutcome::AlreadyKnown => Ok(()),
+            }
+            .with_context(|| format!("block {} stored but not audited", block.hash))?;
+        }
+        Ok(outcome)
+    }
+
+    fn stage_miner_deltas(
+        &self,
+        batch: &mut sled::Batch,
+        deltas: HashMap<&Address, i64>,
+    ) -> Result<()> {
+        for (miner, delta) in deltas {
+            let count = self.miner_block_count(miner)? as i64 + delta;
+            batch.insert(
+                prefixed(MINER_STATS_PREFIX, miner.as_str()).as_bytes(),
+                &(count.max(0) as u64).to_be_bytes(),
+            );
+        }
+        Ok(())
+    }
+
+    /// For a block whose branch is about to overtake the tip, returns the
+    /// best-chain blocks it disconnects (tip first) and the branch blocks
+    /// it connects (fork point first, ending with `block`).
+    fn fork_branches(&self, block: &Block) -> Result<(Vec<Block>, Vec<Block>)> {
+        let mut connected = vec![block.clone()];
+        let mut cursor = block.header.parent_hash.clone();
+        while !self.on_best_chain(&cursor)? {
+            let b = self
+                .get_block(&cursor)?
+                .ok_or_else(|| anyhow!("missing block {} on side branch", cursor))?;
+            cursor = b.header.parent_hash.clone();
+            connected.push(b);
+        }
+        connected.reverse();
+
+        let fork_height = self
+            .height_of(&cursor)?
+            .ok_or_else(|| anyhow!("missing height index for {}", cursor))?;
+        let mut disconnected = Vec::new();
+        let mut h = self.tip_height()?.unwrap_or(0);
+        while h > fork_height {
+            let hash = self
+                .hash_at_height(h)?
+                .ok_or_else(|| anyhow!("height index has no block at {}", h))?;
+            let b = self
+                .get_block(&hash)?
+                .ok_or_else(|| anyhow!("missing best-chain block {}", hash))?;
+            disconnected.push(b);
+            h -= 1;
+        }
+        Ok((disconnected, connected))
+    }
+}
+
+impl Store for ChainDB {
+    fn save_block(&self, block: &Block) -> Result<()> {
+        self.connect_block(block).map(|_| ())
+    }
+
+    /// Runs standalone validation, then stores the block and resolves any
+    /// fork, reporting which happened.
+    fn accept_block(&self, block: &Block, params: &ChainParams) -> Result<AcceptOutcome> {
+        if self.db.get(block.hash.as_bytes())?.is_some() {
+            return Ok(AcceptOutcome::AlreadyKnown);
+        }
+        validate_block(block, params)?;
+        check_retarget(
+            block,
+            self.next_difficulty(&block.header.parent_hash, params)?,
+        )?;
+        self.connect_block(block)
+    }
+
+    /// Reads only the headers in the retarget window, using the height
+    /// index instead of a walk to genesis.
+    fn next_difficulty(&self, parent_hash: &str, params: &ChainParams) -> Result<u32> {
+        if parent_hash == GENESIS_PARENT {
+            return Ok(params.genesis_difficulty);
+        }
+        let parent_height = self
+            .height_of(parent_hash)?
+            .ok_or_else(|| anyhow!("unknown parent {}", parent_hash))?;
+        let window = params.retarget.window(parent_height + 1);
+        let mut headers = Vec::with_capacity(window);
+        let mut hash = parent_hash.to_string();
+        while headers.len() < window && hash != GENESIS_PARENT {
+            let header = self
+                .header(&hash)?
+                .ok_or_else(|| anyhow!("missing ancestor {}", hash))?;
+            hash = header.parent_hash.clone();
+            headers.push(header);
+        }
+        headers.reverse();
+        Ok(params.retarget.next_difficulty(&headers, params))
+    }
+
+    fn get_block(&self, hash: &str) -> Result<Option<Block>> {
+        match self.db.get(hash.as_bytes())? {
+            Some(bv) => Ok(Some(serde_json::from_slice(&bv)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn block_at_height(&self, height: u64) -> Result<Option<Block>> {
+        match self.hash_at_height(height)? {
+            Some(hash) => self.get_block(&hash),
+            None => Ok(None),
+        }
+    }
+
+    fn latest_hash(&self) -> Result<Option<String>> {
+        match self.db.get(LATEST_KEY)? {
+            Some(v) => Ok(Some(String::from_utf8(v.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn schema_version(&self) -> Result<Option<u32>> {
+        match self.db.get(SCHEMA_VERSION_KEY)? {
+            Some(v) => {
+                let bytes: [u8; 4] = v
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| anyhow!("schema version has {} bytes", v.len()))?;
+                Ok(Some(u32::from_be_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn expected_schema_version(&self) -> u32 {
+        SCHEMA_VERSION
+    }
+
+    /// Side-branch blocks above `height` are deleted too. Checkpointed
+    /// blocks are final, so `height` may not be below the latest
+    /// checkpoint. The rollback is one batch and is written to the audit
+    /// log as a reorg marker.
+    fn rollback_to(&self, height: u64) -> Result<u64> {
+        let _tip = self.tip_lock.lock().unwrap();
+        let tip_height = self
+            .tip_height()?
+            .ok_or_else(|| anyhow!("chain is empty"))?;
+        ensure!(
+            height <= tip_height,
+            "cannot roll back to height {}: the tip is at {}",
+            height,
+            tip_height
+        );
+        if let Some((checkpoint, hash)) = self.latest_checkpoint()? {
+            ensure!(
+                height >= checkpoint,
+                "cannot roll back to height {}: below checkpoint {} at {}",
+                height,
+                hash,
+                checkpoint
+            );
+        }
+        let new_tip = self
+            .hash_at_height(height)?
+            .ok_or_else(|| anyhow!("height index has no block at {}", height))?;
+
+        let mut batch = sled::Batch::default();
+        let mut disconnected = Vec::new();
+        for h in (height + 1..=tip_height).rev() {
+            let block = self
+                .block_at_height(h)?
+                .ok_or_else(|| anyhow!("missing best-chain block at height {}", h))?;
+            batch.remove(height_key(h));
+            disconnected.push(block);
+        }
+        let mut removed = 0;
+        for item in self.db.scan_prefix(BLOCK_HEIGHT_PREFIX.as_bytes()) {
+            let (k, v) = item?;
+            if decode_u64(&v)? <= height {
+                continue;
+            }
+            let hash = std::str::from_utf8(&k[BLOCK_HEIGHT_PREFIX.len()..])?;
+            let header = self
+                .header(hash)?
+                .ok_or_else(|| anyhow!("height index names missing block {}", hash))?;
+            stage_block_removal(&mut batch, &header, hash);
+            removed += 1;
+        }
+        let mut miner_deltas: HashMap<&Address, i64> = HashMap::new();
+        for b in &disconnected {
+            *miner_deltas.entry(&b.header.miner).or_default() -= 1;
+        }
+        self.stage_miner_deltas(&mut batch, miner_deltas)?;
+        batch.insert(LATEST_KEY, new_tip.as_bytes());
+
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        if let Some(audit) = &self.audit {
+            audit
+                .lock()
+                .unwrap()
+                .record_reorg(height, &disconnected)
+                .context("rollback applied but not audited")?;
+        }
+        Ok(removed)
+    }
+
+    /// Recomputes every derived index from the stored blocks and the
+    /// `latest` key, and lists where the stored indexes differ. Nothing
+    /// is modified. `miner_stats/` is not checked: blocks saved before it
+    /// existed are legitimately uncounted.
+    fn verify_indexes(&self) -> Result<IndexReport> {
+        let derived = self.derive_indexes()?;
+        let mut found = Vec::new();
+        let mut report = |index: &'static str, problem: String| found.push((index, problem));
+
+        for hash in &derived.detached {
+            report("blocks", format!("{} does not connect to genesis", hash));
+        }
+        for (height, hash) in derived.best.iter().enumerate() {
+            match self.hash_at_height(height as u64)? {
+                Some(stored) if stored == *hash => {}
+                Some(stored) => report(
+                    "height",
+                    format!("{} holds {}, expected {}", height, stored, hash),
+                ),
+                None => report(
+                    "height",
+                    format!("{} is missing, expected {}", height, hash),
+                ),
+            }
+        }
+        for item in self.db.scan_prefix(HEIGHT_PREFIX.as_bytes()) {
+            let height = decode_u64(&item?.0[HEIGHT_PREFIX.len()..])?;
+            if height >= derived.best.len() as u64 {
+                report("height", format!("{} is above the tip", height));
+            }
+        }
+        for (hash, header) in &derived.headers {
+            match (derived.heights.get(hash), self.height_of(hash)?) {
+                (Some(want), Some(got)) if *want != got => report(
+                    "blockheight",
+                    format!("{} is {}, expected {}", hash, got, want),
+                ),
+                (Some(_), None) => report("blockheight", format!("{} is missing", hash)),
+                _ => {}
+            }
+            match (derived.work.get(hash), self.stored_work(hash)?) {
+                (Some(want), Some(got)) if *want != got => {
+                    report("work", format!("{} is {}, expected {}", hash, got, want))
+                }
+                (Some(_), None) => report("work", format!("{} is missing", hash)),
+                _ => {}
+            }
+            match self.db.get(prefixed(HEADER_PREFIX, hash).as_bytes())? {
+                Some(v) if *v == *serde_json::to_vec(header)? => {}
+                Some(_) => report("header", format!("{} does not match the block", hash)),
+                None => report("header", format!("{} is missing", hash)),
+            }
+            if self
+                .db
+                .get(child_key(&header.parent_hash, hash).as_bytes())?
+                .is_none()
+            {
+                report(
+                    "children",
+                    format!("{} -> {} is missing", header.parent_hash, hash),
+                );
+            }
+        }
+        for prefix in [BLOCK_HEIGHT_PREFIX, WORK_PREFIX, HEADER_PREFIX] {
+            for item in self.db.scan_prefix(prefix.as_bytes()) {
+                let hash = std::str::from_utf8(&item?.0[prefix.len()..])?.to_string();
+                if !derived.headers.contains_key(&hash) {
+                    report(
+                        prefix.trim_end_matches('/'),
+                        format!("{} has no stored block", hash),
+                    );
+                }
+            }
+        }
+        for item in self.db.scan_prefix(CHILDREN_PREFIX.as_bytes()) {
+            let link = std::str::from_utf8(&item?.0[CHILDREN_PREFIX.len()..])?.to_string();
+            let (parent, child) = link.split_once('/').unwrap_or((&link, ""));
+            let linked = derived
+                .headers
+                .get(child)
+                .is_some_and(|h| h.parent_hash == parent);
+            if !linked {
+                report(
+                    "children",
+                    format!("{} -> {} has no stored block", parent, child),
+                );
+            }
+        }
+        Ok(IndexReport {
+            blocks_checked: derived.headers.len() as u64,
+            discrepancies: found,
+        })
+    }
+}
+
+/// Forward walk returned by `ChainDB::walk_from`.
+pub struct ForwardIter<'a> {
+    db: &'a ChainDB,
+    next: Option<String>,
+}
+
+impl Iterator for ForwardIter<'_> {
+    type Item = Result<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let hash = self.next.take()?;
+        let block = match self.db.get_block(&hash) {
+            Ok(Some(block)) => block,
+            Ok(None) => return Some(Err(anyhow!("missing block {}", hash))),
+            Err(e) => return Some(Err(e)),
+        };
+        match self.db.best_child(&hash) {
+            Ok(next) => self.next = next,
+            Err(e) => return Some(Err(e)),
+        }
+        Some(Ok(block))
+    }
+}
+
+pub struct ChainIter<'a, S: Store + ?Sized> {
+    store: &'a S,
+    next: Option<String>,
+}
+
+impl<'a, S: Store + ?Sized> ChainIter<'a, S> {
+    pub fn new(store: &'a S) -> Result<Self> {
+        let next = store.latest_hash()?;
+        Ok(Self { store, next })
+    }
+}
+
+impl<S: Store + ?Sized> Iterator for ChainIter<'_, S> {
+    type Item = Result<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let hash = self.next.take()?;
+        let block = match self.store.get_block(&hash) {
+            Ok(Some(block)) => block,
+            Ok(None) => return Some(Err(anyhow!("missing block {}", hash))),
+            Err(e) => return Some(Err(e)),
+        };
+        if block.hash != hash {
+            return Some(Err(anyhow!(
+                "block stored under {} claims hash {}",
+                hash,
+                block.hash
+            )));
+        }
+        if block.header.parent_hash != GENESIS_PARENT {
+            self.next = Some(block.header.parent_hash.clone());
+        }
+        Some(Ok(block))
+    }
+}
+
+/// Result of `Store::block_interval_stats`, in seconds.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IntervalStats {
+    pub count: usize,
+    pub mean: f64,
+    pub min: i64,
+    pub max: i64,
+    pub variance: f64,
+    pub std_dev: f64,
+}
+
+/// Result of `Store::startup_report`.
+#[derive(Serialize, Debug, Clone)]
+pub struct StartupReport {
+    pub genesis_ok: bool,
+    pub verified_height: Option<u64>,
+    pub indexes_consistent: bool,
+    pub schema_version: Option<u32>,
+    pub expected_schema_version: u32,
+    pub errors: Vec<String>,
+}
+
+impl StartupReport {
+    pub fn is_ready(&self) -> bool {
+        self.genesis_ok
+            && self.verified_height.is_some()
+            && self.indexes_consistent
+            && self.schema_version == Some(self.expected_schema_version)
+    }
+}
+
+/// Result of `ChainDB::get_block_meta`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockMeta {
+    pub height: u64,
+    /// Cumulative work up to and including this block.
+    pub work: u128,
+    pub on_best_chain: bool,
+    /// Best-chain blocks from this one to the tip, counting both; zero
+    /// off the best chain.
+    pub confirmations: u64,
+    pub tx_count: usize,
+    /// Stored size in bytes.
+    pub size: usize,
+    pub timestamp: i64,
+    pub difficulty: u32,
+}
+
+/// Result of `ChainDB::verify_indexes`.
+#[derive(Debug, Clone)]
+pub struct IndexReport {
+    pub blocks_checked: u64,
+    /// `(index, problem)` for every entry that differs from the blocks.
+    pub discrepancies: Vec<(&'static str, String)>,
+}
+
+impl IndexReport {
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+impl fmt::Display for IndexReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} blocks checked, {} index discrepancies",
+            self.blocks_checked,
+            self.discrepancies.len()
+        )?;
+        for (index, problem) in &self.discrepancies {
+            write!(f, "\n  {}: {}", index, problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Indexes recomputed from the blocks; see `ChainDB::derive_indexes`.
+struct DerivedIndexes {
+    headers: HashMap<String, BlockHeader>,
+    heights: HashMap<String, u64>,
+    work: HashMap<String, u128>,
+    /// Best-chain hashes, genesis first, ending at `latest`.
+    best: Vec<String>,
+    /// Blocks whose ancestry is missing before genesis.
+    detached: Vec<String>,
+}
+
+impl fmt::Display for StartupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verified = match self.verified_height {
+            Some(h) => format!("verified to height {}", h),
+            None => "not verified".to_string(),
+        };
+        let schema = match self.schema_version {
+            Some(v) => format!("v{}", v),
+            None => "missing".to_string(),
+        };
+        write!(
+            f,
+            "genesis {}, chain {}, indexes {}, schema {} -> {}",
+            if self.genesis_ok { "ok" } else { "FAILED" },
+            verified,
+            if self.indexes_consistent {
+                "consistent"
+            } else {
+                "INCONSISTENT"
+            },
+            schema,
+            if self.is_ready() {
+                "ready"
+            } else {
+                "NOT READY"
+            }
+        )?;
+        for e in &self.errors {
+            write!(f, "\n  - {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+// -----------------------------
+// Audit log
+// -----------------------------
+
+/// Append-only text log of best-chain changes, kept outside the block
+/// store. Each line is one of
+///
+/// ```text
+/// block <height> <hash> <timestamp> <tx_count>
+/// reorg <fork_height> <disconnected_count> <old_tip_hash>
+/// ```
+///
+/// A reorg line is followed by `block` lines for the newly connected
+/// blocks; earlier lines are never rewritten. Every write is fsynced.
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    fn append(&mut self, line: &str) -> Result<()> {
+        writeln!(self.file, "{}", line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn record_block(&mut self, height: u64, block: &Block) -> Result<()> {
+        self.append(&format!(
+            "block {} {} {} {}",
+            height,
+            block.hash,
+            block.header.timestamp,
+            block.txs.len()
+        ))
+    }
+
+    /// `disconnected` runs from the old tip down, as in `AcceptOutcome`.
+    pub fn record_reorg(&mut self, fork_height: u64, disconnected: &[Block]) -> Result<()> {
+        let old_tip = disconnected.first().map(|b| b.hash.as_str()).unwrap_or("-");
+        self.append(&format!(
+            "reorg {} {} {}",
+            fork_height,
+            disconnected.len(),
+            old_tip
+        ))
+    }
+}
+
+// -----------------------------
+// Header export (SPV bootstrap)
+// -----------------------------
+
+/// Leading bytes of a header export; the byte after it is the format
+/// version.
+const HEADERS_MAGIC: &[u8; 4] = b"HDRS";
+const HEADERS_FORMAT: u8 = 1;
+/// A hash field that is 64 lowercase hex characters, stored as 32 bytes.
+const FIELD_HASH: u8 = 1;
+/// Any other string field (the genesis parent, an empty merkle root),
+/// stored as a length byte and its text.
+pub(crate) const FIELD_TEXT: u8 = 0;
+
+fn write_hash_field(w: &mut impl Write, s: &str) -> Result<()> {
+    match hex::decode(s) {
+        Ok(bytes) if bytes.len() == 32 && hex::encode(&bytes) == s => {
+            w.write_all(&[FIELD_HASH])?;
+            w.write_all(&bytes)?;
+        }
+        _ => write_text_field(w, FIELD_TEXT, s)?,
+    }
+    Ok(())
+}
+
+pub(crate) fn write_text_field(w: &mut impl Write, tag: u8, s: &str) -> Result<()> {
+    let len = u8::try_from(s.len()).map_err(|_| anyhow!("field {:?} is too long", s))?;
+    w.write_all(&[tag, len])?;
+    w.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+/// Writes `header` in the export encoding: fixed-width big-endian
+/// integers, hashes as raw bytes and the miner as short text.
+pub(crate) fn write_header(w: &mut impl Write, header: &BlockHeader) -> Result<()> {
+    w.write_all(&header.version.to_be_bytes())?;
+    write_hash_field(w, &header.parent_hash)?;
+    write_hash_field(w, &header.merkle_root)?;
+    w.write_all(&header.timestamp.to_be_bytes())?;
+    w.write_all(&header.nonce.to_be_bytes())?;
+    w.write_all(&header.difficulty.to_be_bytes())?;
+    write_text_field(w, FIELD_TEXT, header.miner.as_str())
+}
+
+pub(crate) fn read_array<const N: usize>(r: &mut impl io::Read) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub(crate) fn read_field(r: &mut impl io::Read) -> Result<String> {
+    let [tag] = read_array::<1>(r)?;
+    match tag {
+        FIELD_HASH => Ok(hex::encode(read_array::<32>(r)?)),
+        FIELD_TEXT => {
+            let [len] = read_array::<1>(r)?;
+            let mut text = vec![0u8; len as usize];
+            r.read_exact(&mut text)?;
+            Ok(String::from_utf8(text)?)
+        }
+        other => Err(anyhow!("unknown header field tag {}", other)),
+    }
+}
+
+/// Reads one header, or `None` at a clean end of stream.
+pub(crate) fn read_header(r: &mut impl io::Read) -> Result<Option<BlockHeader>> {
+    let mut version = [0u8; 4];
+    match r.read(&mut version[..1])? {
+        0 => return Ok(None),
+        _ => r.read_exact(&mut version[1..])?,
+    }
+    Ok(Some(BlockHeader {
+        version: u32::from_be_bytes(version),
+        parent_hash: read_field(r)?,
+        merkle_root: read_field(r)?,
+        timestamp: i64::from_be_bytes(read_array(r)?),
+        nonce: u64::from_be_bytes(read_array(r)?),
+        difficulty: u32::from_be_bytes(read_array(r)?),
+        miner: Address::parse(&read_field(r)?)?,
+    }))
+}
+
+/// Header-only view of a chain, as imported from a header export.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderChain {
+    headers: Vec<BlockHeader>,
+    hashes: Vec<String>,
+}
+
+impl HeaderChain {
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn header_at(&self, height: u64) -> Option<&BlockHeader> {
+        self.headers.get(height as usize)
+    }
+
+    pub fn hash_at(&self, height: u64) -> Option<&str> {
+        self.hashes.get(height as usize).map(String::as_str)
+    }
+
+    pub fn tip_hash(&self) -> Option<&str> {
+        self.hashes.last().map(String::as_str)
+    }
+}
+
+/// Reads a header export for `params`' network, checking that it starts at
+/// that network's genesis header, that each header links to the one
+/// before it, and that every later header carries the difficulty the
+/// retarget rule requires and meets it.
+pub fn import_headers(mut r: impl io::Read, params: &ChainParams) -> Result<HeaderChain> {
+    let magic = read_array::<4>(&mut r).context("reading header export magic")?;
+    ensure!(&magic == HEADERS_MAGIC, "not a header export");
+    let [format] = read_array::<1>(&mut r)?;
+    ensure!(
+        format == HEADERS_FORMAT,
+        "unsupported header export format {}",
+        format
+    );
+    let genesis = make_genesis(params);
+    let mut chain = HeaderChain::default();
+    while let Some(header) = read_header(&mut r)? {
+        let height = chain.len();
+        let hash = hash_block_header(&header);
+        // a header is checked as a block with no transactions
+        let block = Block {
+            header,
+            txs: Vec::new(),
+            hash,
+        };
+        if height == 0 {
+            ensure!(
+                block.hash == genesis.hash,
+                "export starts at {}, not this network's genesis {}",
+                block.hash,
+                genesis.hash
+            );
+        } else {
+            let expected_parent = &chain.hashes[height - 1];
+            ensure!(
+                block.header.parent_hash == *expected_parent,
+                "header at height {} has parent {}, expected {}",
+                height,
+                block.header.parent_hash,
+                expected_parent
+            );
+            let window = params.retarget.window(height as u64).min(height);
+            let expected = params
+                .retarget
+                .next_difficulty(&chain.headers[height - window..], params);
+            check_difficulty(&block, params)
+                .and_then(|()| check_retarget(&block, expected))
+                .and_then(|()| check_pow(&block))
+                .with_context(|| format!("header {} at height {}", block.hash, height))?;
+        }
+        chain.headers.push(block.header);
+        chain.hashes.push(block.hash);
+    }
+    Ok(chain)
+}
+
+// -----------------------------
+// Storage wrapper (flat files)
+// -----------------------------
+
+const FILE_STORE_INDEX: &str = "index";
+const FILE_STORE_VERSION: &str = "version";
+/// Version of the flat-file layout, stored in the `version` file.
+pub const FILE_STORE_SCHEMA_VERSION: u32 = 1;
+
+/// Append-only archival backend: block `n` of the chain lives in
+/// `blocks/<n>.bin` and `index` lists one block hash per line in height
+/// order. Only blocks extending the current tip can be saved.
+pub struct FileStore {
+    dir: PathBuf,
+    index: Mutex<FileIndex>,
+}
+
+#[derive(Default)]
+struct FileIndex {
+    hashes: Vec<String>,
+    heights: HashMap<String, u64>,
+}
+
+impl FileIndex {
+    fn push(&mut self, hash: String) {
+        self.heights.insert(hash.clone(), self.hashes.len() as u64);
+        self.hashes.push(hash);
+    }
+}
+
+impl FileStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("blocks"))?;
+        let version_path = dir.join(FILE_STORE_VERSION);
+        if !version_path.exists() {
+            fs::write(&version_path, FILE_STORE_SCHEMA_VERSION.to_string())?;
+        }
+
+        let mut index = FileIndex::default();
+        let index_path = dir.join(FILE_STORE_INDEX);
+        if index_path.exists() {
+            for line in fs::read_to_string(&index_path)?.lines() {
+                index.push(line.trim().to_string());
+            }
+        }
+        let store = Self {
+            dir,
+            index: Mutex::new(FileIndex::default()),
+        };
+
+        // rollbacks delete block files from the top down before rewriting
+        // the index, so an interrupted one leaves index entries past the
+        // last block file; drop them
+        if let Some(missing) =
+            (0..index.hashes.len()).find(|&h| !store.block_path(h as u64).exists())
+        {
+            let mut kept = FileIndex::default();
+            for hash in index.hashes.drain(..missing) {
+                kept.push(hash);
+            }
+            index = kept;
+            store.write_index(&index.hashes)?;
+        }
+
+        // a crash between writing a block file and appending its index line
+        // leaves block files past the end of the index; pick them up again
+        let mut height = index.hashes.len() as u64;
+        while let Some(block) = store.read_height(height)? {
+            store.append_index(&block.hash)?;
+            index.push(block.hash);
+            height += 1;
+        }
+        *store.index.lock().unwrap() = index;
+        Ok(store)
+    }
+
+    fn block_path(&self, height: u64) -> PathBuf {
+        self.dir.join("blocks").join(format!("{}.bin", height))
+    }
+
+    fn read_height(&self, height: u64) -> Result<Option<Block>> {
+        match fs::read(self.block_path(height)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn append_index(&self, hash: &str) -> Result<()> {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(FILE_STORE_INDEX))?;
+        writeln!(f, "{}", hash)?;
+        f.sync_all()?;
+        Ok(())
+    }
+
+    fn write_index(&self, hashes: &[String]) -> Result<()> {
+        let path = self.dir.join(FILE_STORE_INDEX);
+        let tmp = path.with_extension("tmp");
+        let mut f = File::create(&tmp)?;
+        for hash in hashes {
+            writeln!(f, "{}", hash)?;
+        }
+        f.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+impl Store for FileStore {
+    fn save_block(&self, block: &Block) -> Result<()> {
+        let mut index = self.index.lock().unwrap();
+        if index.heights.contains_key(&block.hash) {
+            return Ok(());
+        }
+        let expected_parent = index
+            .hashes
+            .last()
+            .map(String::as_str)
+            .unwrap_or(GENESIS_PARENT);
+        ensure!(
+            block.header.parent_hash == expected_parent,
+            "file store is append-only: block {} does not extend tip {}",
+            block.hash,
+            expected_parent
+        );
+
+        let height = index.hashes.len() as u64;
+        let path = self.block_path(height);
+        let tmp = path.with_extension("bin.tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(&serde_json::to_vec(block)?)?;
+        f.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        self.append_index(&block.hash)?;
+        index.push(block.hash.clone());
+        Ok(())
+    }
+
+    /// Reads only the block files in the retarget window, finding the
+    /// parent's height in the in-memory index.
+    fn next_difficulty(&self, parent_hash: &str, params: &ChainParams) -> Result<u32> {
+        if parent_hash == GENESIS_PARENT {
+            return Ok(params.genesis_difficulty);
+        }
+        let parent_height = self
+            .index
+            .lock()
+            .unwrap()
+            .heights
+            .get(parent_hash)
+            .copied()
+            .ok_or_else(|| anyhow!("unknown parent {}", parent_hash))?;
+        let window = params.retarget.window(parent_height + 1) as u64;
+        let mut headers = Vec::new();
+        let next_height = parent_height + 1;
+        for height in next_height.saturating_sub(window)..next_height {
+            let block = self
+                .read_height(height)?
+                .ok_or_else(|| anyhow!("missing block file for height {}", height))?;
+            headers.push(block.header);
+        }
+        Ok(params.retarget.next_difficulty(&headers, params))
+    }
+
+    fn get_block(&self, hash: &str) -> Result<Option<Block>> {
+        let height = self.index.lock().unwrap().heights.get(hash).copied();
+        match height {
+            Some(h) => self.read_height(h),
+            None => Ok(None),
+        }
+    }
+
+    fn block_at_height(&self, height: u64) -> Result<Option<Block>> {
+        let known = self.index.lock().unwrap().hashes.len() as u64;
+        if height >= known {
+            return Ok(None);
+        }
+        self.read_height(height)
+    }
+
+    fn latest_hash(&self) -> Result<Option<String>> {
+        Ok(self.index.lock().unwrap().hashes.last().cloned())
+    }
+
+    fn schema_version(&self) -> Result<Option<u32>> {
+        match fs::read_to_string(self.dir.join(FILE_STORE_VERSION)) {
+            Ok(v) => Ok(Some(v.trim().parse()?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn expected_schema_version(&self) -> u32 {
+        FILE_STORE_SCHEMA_VERSION
+    }
+
+    fn rollback_to(&self, height: u64) -> Result<u64> {
+        let mut index = self.index.lock().unwrap();
+        let len = index.hashes.len() as u64;
+        ensure!(
+            height < len,
+            "cannot roll back to height {}: the tip is at {}",
+            height,
+            len as i64 - 1
+        );
+        for h in (height + 1..len).rev() {
+            fs::remove_file(self.block_path(h))?;
+        }
+        let mut kept = FileIndex::default();
+        for hash in index.hashes.drain(..=height as usize) {
+            kept.push(hash);
+        }
+        self.write_index(&kept.hashes)?;
+        *index = kept;
+        Ok(len - height - 1)
+    }
+}
+
+// -----------------------------
+// Mining / PoW
+// -----------------------------
+
+pub fn meets_difficulty(hex_hash: &str, difficulty: u32) -> bool {
+    // simple target: count leading zero nibbles (hex characters)
+    let needed = (difficulty as usize) / 4; // approximate
+    hex_hash.starts_with(&"0".repeat(needed))
+}
+
+/// Difficulty a hash actually achieves: its number of leading zero bits.
+/// `meets_difficulty(hash, d)` holds for every `d` up to this value, so
+/// comparing it with the header difficulty shows how lucky a block was.
+/// Malformed hex stops the count at the first bad digit.
+pub fn hash_to_difficulty(hex_hash: &str) -> u32 {
+    let mut bits = 0;
+    for c in hex_hash.chars() {
+        match c.to_digit(16) {
+            Some(0) => bits += 4,
+            Some(digit) => return bits + digit.leading_zeros() - 28,
+            None => break,
+        }
+    }
+    bits
+}
+
+/// Difficulties below one full nibble accept any hash.
+pub fn requires_no_work(difficulty: u32) -> bool {
+    difficulty < 4
+}
+
+/// An unmined block: header fields and transactions fixed, nonce not yet
+/// searched.
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub header: BlockHeader,
+    pub txs: Vec<Transaction>,
+}
+
+impl BlockTemplate {
+    pub fn mine(&self, stride: NonceStride) -> Block {
+        mine_block_strided(&self.header, &self.txs, stride)
+    }
+}
+
+/// Splits the nonce space between `miner_count` cooperating miners: miner
+/// `miner_id` only tries nonces congruent to its id modulo the count, so
+/// miners fed the same template never repeat each other's work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonceStride {
+    miner_id: u64,
+    miner_count: u64,
+}
+
+impl NonceStride {
+    pub fn new(miner_id: u64, miner_count: u64) -> Result<Self> {
+        ensure!(
+            miner_id < miner_count,
+            "miner id {} is not below miner count {}",
+            miner_id,
+            miner_count
+        );
+        Ok(Self {
+            miner_id,
+            miner_count,
+        })
+    }
+
+    /// Number of nonces in each miner's share. Shares stop short of
+    /// wrapping so they stay disjoint for counts that do not divide 2^64.
+    pub fn slots(&self) -> u64 {
+        u64::MAX / self.miner_count
+    }
+
+    /// The `k`th nonce in this miner's share, for `k < slots()`.
+    pub fn nonce(&self, k: u64) -> u64 {
+        k * self.miner_count + self.miner_id
+    }
+}
+
+impl Default for NonceStride {
+    /// A lone miner owning the whole nonce space.
+    fn default() -> Self {
+        Self {
+            miner_id: 0,
+            miner_count: 1,
+        }
+    }
+}
+
+/// Assembles a block on `parent` from exactly `txs`, in order, checking
+/// them as a set against the block-level rules. Nothing is drawn from a
+/// mempool and nothing is random, so the same inputs give the same
+/// template.
+pub fn build_block(
+    parent: &Block,
+    txs: Vec<Transaction>,
+    miner: Address,
+    version: u32,
+    now: i64,
+    difficulty: u32,
+    params: &ChainParams,
+) -> Result<BlockTemplate> {
+    let block = Block {
+        header: BlockHeader {
+            version,
+            parent_hash: parent.hash.clone(),
+            merkle_root: merkle_root(&txs),
+            timestamp: now,
+            nonce: 0,
+            difficulty,
+            miner,
+        },
+        txs,
+        hash: String::new(),
+    };
+    check_tx_count(&block)?;
+    check_dust(&block, params)?;
+    Ok(BlockTemplate {
+        header: block.header,
+        txs: block.txs,
+    })
+}
+
+/// `base` shifted by a uniformly random amount in `[-jitter, +jitter]`,
+/// never below zero. Jitter beyond `u64::MAX / 2` milliseconds is capped
+/// there, so the range and the sum cannot overflow.
+pub fn jittered(base: Duration, jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        return base;
+    }
+    let jitter = jitter.as_millis().min(u128::from(u64::MAX / 2)) as u64;
+    let offset = rand::thread_rng().gen_range(0..=2 * jitter);
+    base.saturating_add(Duration::from_millis(offset))
+        .saturating_sub(Duration::from_millis(jitter))
+}
+
+pub fn mine_block(header_template: &BlockHeader, txs: &[Transaction]) -> Block {
+    mine_block_strided(header_template, txs, NonceStride::default())
+}
+
+/// Mines only the nonces in `stride`'s share, walking them from a random
+/// starting point.
+pub fn mine_block_strided(
+    header_template: &BlockHeader,
+    txs: &[Transaction],
+    stride: NonceStride,
+) -> Block {
+    let mut header = header_template.clone();
+    // recompute merkle root in case txs changed
+    header.merkle_root = merkle_root(txs);
+    let slots = stride.slots();
+    let mut k = rand::thread_rng().gen_range(0..slots);
+    loop {
+        header.nonce = stride.nonce(k);
+        let h = hash_block_header(&header);
+        if meets_difficulty(&h, header.difficulty) {
+            return Block {
+                header,
+                txs: txs.to_vec(),
+                hash: h,
+            };
+        }
+        k = (k + 1) % slots;
+    }
+}
+
+// -----------------------------
+// Mempool
+// -----------------------------
+
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    /// Tip height when the transaction entered the pool.
+    pub first_seen_height: u64,
+}
+
+/// Mempool policy capping how much one address may send within a sliding
+/// window of blocks. Written `AMOUNT/BLOCKS` on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VelocityLimit {
+    pub max_amount: u64,
+    pub window_blocks: u64,
+}
+
+impl std::str::FromStr for VelocityLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (amount, blocks) = s
+            .split_once('/')
+            .ok_or_else(|| anyhow!("expected AMOUNT/BLOCKS, found {:?}", s))?;
+        let limit = Self {
+            max_amount: amount.parse()?,
+            window_blocks: blocks.parse()?,
+        };
+        ensure!(
+            limit.window_blocks > 0,
+            "the velocity window must be at least one block"
+        );
+        Ok(limit)
+    }
+}
+
+pub struct Mempool {
+    entries: Vec<MempoolEntry>,
+    /// Evict transactions that have waited more than this many blocks.
+    max_tx_blocks: Option<u64>,
+    /// Most pending transactions one sender may have in the pool.
+    max_ancestors: Option<usize>,
+    velocity: Option<VelocityLimit>,
+    /// `(tip height, sender, amount)` for every admission still inside
+    /// the velocity window, oldest first.
+    recent_spends: VecDeque<(u64, Address, u64)>,
+}
+
+impl Mempool {
+    pub fn new(max_tx_blocks: Option<u64>) -> Self {
+        Self {
+            entries: Vec::new(),
+            max_tx_blocks,
+            max_ancestors: None,
+            velocity: None,
+            recent_spends: VecDeque::new(),
+        }
+    }
+
+    /// Transactions have no nonces, so every pending transaction from a
+    /// sender counts as an ancestor of the sender's next one.
+    pub fn with_max_ancestors(mut self, max: usize) -> Self {
+        self.max_ancestors = Some(max);
+        self
+    }
+
+    /// Caps what each address may get admitted per window. Admissions
+    /// count whether or not they have since been mined; blocks themselves
+    /// are never held to this policy, and neither are transactions a
+    /// reorg returns to the pool.
+    pub fn with_velocity_limit(mut self, limit: VelocityLimit) -> Self {
+        self.velocity = Some(limit);
+        self
+    }
+
+    pub fn add(&mut self, tx: Transaction, tip_height: u64, params: &ChainParams) -> Result<()> {
+        check_tx_dust(&tx, params)?;
+        if let Some(max) = self.max_ancestors {
+            let pending = self.entries.iter().filter(|e| e.tx.from == tx.from).count();
+            ensure!(
+                pending < max,
+                "{} already has {} pending transactions, the limit is {}",
+                tx.from,
+                pending,
+                max
+            );
+        }
+        if let Some(limit) = self.velocity {
+            while let Some((h, _, _)) = self.recent_spends.front() {
+                if tip_height.saturating_sub(*h) < limit.window_blocks {
+                    break;
+                }
+                self.recent_spends.pop_front();
+            }
+            let spent: u128 = self
+                .recent_spends
+                .iter()
+                .filter(|(_, from, _)| *from == tx.from)
+                .map(|(_, _, amount)| *amount as u128)
+                .sum();
+            ensure!(
+                spent + tx.amount as u128 <= limit.max_amount as u128,
+                "{} has sent {} in the last {} blocks; {} more exceeds the cap of {}",
+                tx.from,
+                spent,
+                limit.window_blocks,
+                tx.amount,
+                limit.max_amount
+            );
+            self.recent_spends
+                .push_back((tip_height, tx.from.clone(), tx.amount));
+        }
+        self.entries.push(MempoolEntry {
+            tx,
+            first_seen_height: tip_height,
+        });
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[MempoolEntry] {
+        &self.entries
+    }
+
+    pub fn txs(&self) -> Vec<Transaction> {
+        self.entries.iter().map(|e| e.tx.clone()).collect()
+    }
+
+    /// Drops every pending transaction that `block` included.
+    pub fn remove_included(&mut self, block: &Block) {
+        self.entries.retain(|e| !block.txs.contains(&e.tx));
+    }
+
+    /// Returns transactions from blocks a reorg disconnected to the pool,
+    /// unless the new branch included them. They were admitted once
+    /// already, so only the dust rule is re-checked: they are not charged
+    /// to the velocity or ancestor limits again. They count as first seen
+    /// at `tip_height`.
+    /// Anything the new branch included is dropped from the pool. Returns
+    /// the number reinserted; outcomes other than a reorg are a no-op.
+    pub fn reconstruct_after_reorg(
+        &mut self,
+        outcome: &AcceptOutcome,
+        tip_height: u64,
+        params: &ChainParams,
+    ) -> usize {
+        let AcceptOutcome::Reorg {
+            disconnected,
+            connected,
+        } = outcome
+        else {
+            return 0;
+        };
+        let included = |tx: &Transaction| connected.iter().any(|b| b.txs.contains(tx));
+        self.entries.retain(|e| !included(&e.tx));
+        let mut reinserted = 0;
+        for tx in disconnected.iter().flat_map(|b| &b.txs) {
+            if included(tx) || self.entries.iter().any(|e| e.tx == *tx) {
+                continue;
+            }
+            if check_tx_dust(tx, params).is_ok() {
+                self.entries.push(MempoolEntry {
+                    tx: tx.clone(),
+                    first_seen_height: tip_height,
+                });
+                reinserted += 1;
+            }
+        }
+        reinserted
+    }
+
+    /// Evicts transactions that have survived more than `max_tx_blocks`
+    /// blocks without inclusion. Returns the number evicted.
+    pub fn evict_expired(&mut self, tip_height: u64) -> usize {
+        let Some(max) = self.max_tx_blocks else {
+            return 0;
+        };
+        let before = self.entries.len();
+        self.entries
+            .retain(|e| tip_height.saturating_sub(e.first_seen_height) <= max);
+        before - self.entries.len()
+    }
+}
+
+// -----------------------------
+// Application (MVP node)
+// -----------------------------
+
+/// Node-local settings (policy, not consensus).
+#[derive(Debug, Clone, Default)]
+pub struct NodeConfig {
+    pub network: Network,
+    /// Block-count lifetime for unincluded mempool transactions.
+    pub mempool_tx_max_blocks: Option<u64>,
+    /// Treatment of unknown fields in blocks and transactions read from
+    /// files.
+    pub decoding: Decoding,
+    /// Per-sender cap on what the mempool admits per block window.
+    pub spend_velocity: Option<VelocityLimit>,
+    /// Per-sender cap on pending mempool transactions.
+    pub max_mempool_ancestors: Option<usize>,
+    pub store: StoreKind,
+    /// Append-only log of best-chain changes (sled store only).
+    pub audit_log: Option<PathBuf>,
+    /// Version bits set in mined headers to signal soft-fork readiness.
+    pub version_bits: u32,
+    /// Roll the chain back to this height before resuming.
+    pub resync_from: Option<u64>,
+    /// Checkpoint the best chain every this many blocks (sled store only).
+    pub auto_checkpoint_interval: Option<u64>,
+    /// Miner recorded in mined headers, overriding the node default.
+    pub mine_address: Option<Address>,
+    /// Genesis spec replacing the network preset's genesis parameters.
+    pub genesis_spec: Option<PathBuf>,
+    /// Retarget rule replacing the network preset's.
+    pub retarget: Option<Retarget>,
+    /// Random spread around the pause between mined blocks, so block
+    /// times are not perfectly regular.
+    pub mining_jitter: Duration,
+    /// This miner's share of the nonce space when several mine one template.
+    pub nonce_stride: NonceStride,
+}
+
+impl NodeConfig {
+    /// Directory of the configured store within the network's data dir.
+    pub fn store_path(&self) -> PathBuf {
+        let name = match self.store {
+            StoreKind::Sled => "chain_db",
+            StoreKind::File => "chain_files",
+        };
+        self.network.data_dir().join(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StoreKind {
+    #[default]
+    Sled,
+    File,
+}
+
+impl std::str::FromStr for StoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sled" => Ok(StoreKind::Sled),
+            "file" => Ok(StoreKind::File),
+            other => Err(anyhow!("unknown store {:?} (expected sled or file)", other)),
+        }
+    }
+}
+
+/// Opens the configured block store under the network's data directory.
+pub fn open_store(config: &NodeConfig) -> Result<Box<dyn Store>> {
+    Ok(match config.store {
+        StoreKind::Sled => {
+            let mut db = ChainDB::open(config.store_path())?;
+            if let Some(path) = &config.audit_log {
+                db = db.with_audit_log(AuditLog::open(path)?);
+            }
+            if let Some(interval) = config.auto_checkpoint_interval {
+                db = db.with_auto_checkpoints(interval);
+            }
+            Box::new(db)
+        }
+        StoreKind::File => {
+            ensure!(
+                config.audit_log.is_none(),
+                "the audit log is only supported with the sled store"
+            );
+            ensure!(
+                config.auto_checkpoint_interval.is_none(),
+                "auto checkpoints are only supported with the sled store"
+            );
+            Box::new(FileStore::open(config.store_path())?)
+        }
+    })
+}
+
+/// Whether `err` was caused by the disk running out of space, so the
+/// caller can wait for space instead of giving up.
+pub fn is_disk_full(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let io_err = match cause.downcast_ref::<sled::Error>() {
+            Some(sled::Error::Io(e)) => Some(e),
+            _ => cause.downcast_ref::<io::Error>(),
+        };
+        io_err.is_some_and(|e| e.kind() == io::ErrorKind::StorageFull)
+    })
+}
+
+/// Editable description of a new network's genesis, as written by
+/// `--genesis-template` and read back by `--genesis-spec`. Balances are
+/// not tracked, so there are no allocations to declare.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GenesisSpec {
+    /// Network magic as 8 hex characters.
+    pub magic: String,
+    pub timestamp: i64,
+    /// Difficulty in the genesis header.
+    pub difficulty: u32,
+    /// Difficulty of every later block; defaults to `difficulty`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_difficulty: Option<u32>,
+}
+
+impl GenesisSpec {
+    /// Spec for `params` with its genesis moved to `timestamp`.
+    pub fn template(params: &ChainParams, timestamp: i64) -> Self {
+        Self {
+            magic: hex::encode(params.magic),
+            timestamp,
+            difficulty: params.genesis_difficulty,
+            block_difficulty: Some(params.difficulty),
+        }
+    }
+
+    pub fn from_json(bytes: &[u8]) -> Result<Self> {
+        let spec: Self = serde_json::from_slice(bytes).context("parsing genesis spec")?;
+        spec.magic_bytes()?;
+        Ok(spec)
+    }
+
+    fn magic_bytes(&self) -> Result<[u8; 4]> {
+        let bytes = hex::decode(&self.magic).context("genesis magic is not hex")?;
+        bytes
+            .try_into()
+            .map_err(|b: Vec<u8>| anyhow!("genesis magic is {} bytes, expected 4", b.len()))
+    }
+
+    /// Overrides the network parameters the spec defines.
+    pub fn apply(&self, params: &mut ChainParams) -> Result<()> {
+        params.magic = self.magic_bytes()?;
+        params.genesis_timestamp = self.timestamp;
+        ensure!(
+            self.difficulty <= MAX_DIFFICULTY,
+            "genesis difficulty {} is above {}",
+            self.difficulty,
+            MAX_DIFFICULTY
+        );
+        let block_difficulty = self.block_difficulty.unwrap_or(self.difficulty);
+        ensure!(
+            params.clamp_difficulty(block_difficulty) == block_difficulty,
+            "block difficulty {} is outside the network's range {}..={}",
+            block_difficulty,
+            params.min_difficulty,
+            params.max_difficulty
+        );
+        params.genesis_difficulty = self.difficulty;
+        params.difficulty = block_difficulty;
+        Ok(())
+    }
+}
+
+/// Builds the network's genesis block. It is deterministic, so every node
+/// on a network starts from the same hash.
+pub fn make_genesis(params: &ChainParams) -> Block {
+    let header = BlockHeader {
+        version: 0,
+        parent_hash: String::from(GENESIS_PARENT),
+        merkle_root: String::from(""),
+        timestamp: params.genesis_timestamp,
+        nonce: 0,
+        difficulty: params.genesis_difficulty,
+        miner: Address::parse("genesis").expect("valid address"),
+    };
+    let txs: Vec<Transaction> = vec![];
+    let mut header_for_hash = header.clone();
+    header_for_hash.merkle_root = merkle_root(&txs);
+    let h = hash_block_header(&header_for_hash);
+    Block {
+        header: header_for_hash,
+        txs,
+        hash: h,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Scratch directory unique to one test, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            static NEXT: AtomicU64 = AtomicU64::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "pow-mvp-{}-{}-{}",
+                name,
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn startup_report_checks_genesis_chain_and_latest() {
+        let dir = TempDir::new("startup");
+        let db = ChainDB::open(dir.path().join("db").to_str().unwrap()).unwrap();
+        let report = db.startup_report().unwrap();
+        assert!(!report.is_ready());
+        assert!(report
+            .errors
+            .contains(&"no latest block recorded".to_string()));
+
+        let genesis = make_genesis(&ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        });
+        db.save_block(&genesis).unwrap();
+        let header = BlockHeader {
+            version: 0,
+            parent_hash: genesis.hash.clone(),
+            merkle_root: String::new(),
+            timestamp: genesis.header.timestamp + 10,
+            nonce: 0,
+            difficulty: 4,
+            miner: addr("miner"),
+        };
+        db.save_block(&mine_block(&header, &[])).unwrap();
+        let report = db.startup_report().unwrap();
+        assert!(report.is_ready(), "{}", report);
+        assert_eq!(report.verified_height, Some(1));
+        assert_eq!(report.schema_version, Some(SCHEMA_VERSION));
+
+        db.db
+            .insert(LATEST_KEY, "ab".repeat(32).as_bytes())
+            .unwrap();
+        let report = db.startup_report().unwrap();
+        assert!(!report.is_ready());
+        assert!(!report.indexes_consistent);
+        // latest names a missing block, so no stored height is on its chain
+        assert!(
+            report
+                .errors
+                .iter()
+                .any(|e| e == "height index: 1 is above the tip"),
+            "{}",
+            report
+        );
+    }
+
+    fn addr(s: &str) -> Address {
+        Address::parse(s).unwrap()
+    }
+
+    fn tx(from: &str, to: &str, amount: u64) -> Transaction {
+        Transaction {
+            from: addr(from),
+            to: addr(to),
+            amount,
+        }
+    }
+
+    #[test]
+    fn evict_expired_counts_blocks_since_first_seen() {
+        let params = ChainParams::default();
+        let mut pool = Mempool::new(Some(2));
+        pool.add(tx("alice", "bob", 1), 10, &params).unwrap();
+        pool.add(tx("carol", "bob", 1), 11, &params).unwrap();
+        assert_eq!(pool.evict_expired(12), 0);
+        assert_eq!(pool.evict_expired(13), 1);
+        assert_eq!(pool.txs(), [tx("carol", "bob", 1)]);
+        assert_eq!(pool.evict_expired(14), 1);
+        assert!(pool.is_empty());
+
+        let mut unlimited = Mempool::new(None);
+        unlimited.add(tx("alice", "bob", 1), 0, &params).unwrap();
+        assert_eq!(unlimited.evict_expired(u64::MAX), 0);
+    }
+
+    /// Mines a block on `parent` at the fixed `params.difficulty`.
+    fn mine_on(parent: &Block, txs: Vec<Transaction>, miner: &str, params: &ChainParams) -> Block {
+        mine_at(parent, txs, miner, params.difficulty, params)
+    }
+
+    /// Mines a block on `parent` one target interval later, at `difficulty`.
+    fn mine_at(
+        parent: &Block,
+        txs: Vec<Transaction>,
+        miner: &str,
+        difficulty: u32,
+        params: &ChainParams,
+    ) -> Block {
+        let timestamp = parent.header.timestamp + params.target_block_time;
+        build_block(parent, txs, addr(miner), 0, timestamp, difficulty, params)
+            .unwrap()
+            .mine(NonceStride::default())
+    }
+
+    /// Names of the standalone checks `block` fails.
+    fn failed_checks(block: &Block, params: &ChainParams) -> Vec<&'static str> {
+        standalone_checks(block, params)
+            .into_iter()
+            .filter(|(_, result)| result.is_err())
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn standalone_checks_name_the_failing_check() {
+        let params = ChainParams {
+            difficulty: 8,
+            ..ChainParams::default()
+        };
+        let genesis = make_genesis(&params);
+        let block = mine_on(&genesis, vec![tx("alice", "bob", 3)], "miner", &params);
+        assert!(failed_checks(&genesis, &params).is_empty());
+        assert!(failed_checks(&block, &params).is_empty());
+
+        let mut tampered = block.clone();
+        tampered.txs[0].amount = 300;
+        assert_eq!(failed_checks(&tampered, &params), ["merkle"]);
+        let mut tampered = block.clone();
+        tampered.header.difficulty = 4;
+        assert_eq!(failed_checks(&tampered, &params), ["hash", "difficulty"]);
+    }
+
+    /// A fresh `ChainDB` holding only the genesis block of `params`.
+    fn chain(name: &str, params: &ChainParams) -> (TempDir, ChainDB, Block) {
+        let dir = TempDir::new(name);
+        let db = ChainDB::open(dir.path().join("db").to_str().unwrap()).unwrap();
+        let genesis = make_genesis(params);
+        db.accept_block(&genesis, params).unwrap();
+        (dir, db, genesis)
+    }
+
+    /// Mines and accepts `n` empty blocks on `parent`, returning them in
+    /// order.
+    fn extend(
+        store: &dyn Store,
+        parent: &Block,
+        n: usize,
+        miner: &str,
+        params: &ChainParams,
+    ) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for _ in 0..n {
+            let parent = blocks.last().unwrap_or(parent);
+            let difficulty = store.next_difficulty(&parent.hash, params).unwrap();
+            let block = mine_at(parent, Vec::new(), miner, difficulty, params);
+            store.accept_block(&block, params).unwrap();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn miner_counts_follow_reorgs() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, genesis) = chain("miner-counts", &params);
+        let best = extend(&db, &genesis, 2, "alice", &params);
+        let side = extend(&db, &best[0], 1, "bob", &params);
+        // bob's side block is not counted until its branch wins
+        assert_eq!(db.miner_block_counts().unwrap(), [(addr("alice"), 2)]);
+        extend(&db, &side[0], 1, "bob", &params);
+        assert_eq!(
+            db.miner_block_counts().unwrap(),
+            [(addr("alice"), 1), (addr("bob"), 2)]
+        );
+        assert_eq!(db.miner_block_count(&addr("genesis")).unwrap(), 0);
+        assert_eq!(db.miner_block_count(&addr("carol")).unwrap(), 0);
+    }
+
+    #[test]
+    fn file_store_round_trips_and_recovers_its_index() {
+        let dir = TempDir::new("file-store");
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let genesis = make_genesis(&params);
+        let blocks = {
+            let store = FileStore::open(dir.path()).unwrap();
+            store.save_block(&genesis).unwrap();
+            let blocks = extend(&store, &genesis, 3, "miner", &params);
+            let stray = mine_on(&genesis, Vec::new(), "other", &params);
+            assert!(store.save_block(&stray).is_err());
+            blocks
+        };
+        let index = dir.path().join(FILE_STORE_INDEX);
+        let reopen = || {
+            let store = FileStore::open(dir.path()).unwrap();
+            assert_eq!(store.latest_hash().unwrap(), Some(blocks[2].hash.clone()));
+            assert_eq!(store.verify_chain().unwrap(), 3);
+            let stored = store.block_at_height(2).unwrap().unwrap();
+            assert_eq!(stored.hash, blocks[1].hash);
+            assert_eq!(
+                store.get_block(&blocks[0].hash).unwrap().unwrap().hash,
+                blocks[0].hash
+            );
+            assert_eq!(
+                store.schema_version().unwrap(),
+                Some(store.expected_schema_version())
+            );
+        };
+        reopen();
+
+        // crash before the index line was appended
+        let lines = fs::read_to_string(&index).unwrap();
+        let kept: Vec<&str> = lines.lines().take(2).collect();
+        fs::write(&index, kept.join("\n") + "\n").unwrap();
+        reopen();
+        // lost index entirely
+        fs::remove_file(&index).unwrap();
+        reopen();
+        assert_eq!(fs::read_to_string(&index).unwrap(), lines);
+
+        // interrupted rollback: the top block file is gone, its line is not
+        fs::remove_file(dir.path().join("blocks").join("3.bin")).unwrap();
+        let store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.latest_hash().unwrap(), Some(blocks[1].hash.clone()));
+        assert!(store.get_block(&blocks[2].hash).unwrap().is_none());
+    }
+
+    fn hashes_of(blocks: &[Block]) -> Vec<&str> {
+        blocks.iter().map(|b| b.hash.as_str()).collect()
+    }
+
+    #[test]
+    fn accept_block_reports_each_outcome() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, genesis) = chain("outcomes", &params);
+        let a1 = mine_on(&genesis, Vec::new(), "alice", &params);
+        assert!(matches!(
+            db.accept_block(&a1, &params).unwrap(),
+            AcceptOutcome::Extended
+        ));
+        assert!(matches!(
+            db.accept_block(&a1, &params).unwrap(),
+            AcceptOutcome::AlreadyKnown
+        ));
+        let a2 = mine_on(&a1, Vec::new(), "alice", &params);
+        db.accept_block(&a2, &params).unwrap();
+
+        let b1 = mine_on(&genesis, Vec::new(), "bob", &params);
+        let b2 = mine_on(&b1, Vec::new(), "bob", &params);
+        let b3 = mine_on(&b2, Vec::new(), "bob", &params);
+        for side in [&b1, &b2] {
+            assert!(matches!(
+                db.accept_block(side, &params).unwrap(),
+                AcceptOutcome::SideBranch
+            ));
+        }
+        match db.accept_block(&b3, &params).unwrap() {
+            AcceptOutcome::Reorg {
+                disconnected,
+                connected,
+            } => {
+                assert_eq!(hashes_of(&disconnected), [&a2.hash, &a1.hash]);
+                assert_eq!(hashes_of(&connected), [&b1.hash, &b2.hash, &b3.hash]);
+            }
+            other => panic!("expected a reorg, got {:?}", other),
+        }
+        assert_eq!(db.latest_hash().unwrap(), Some(b3.hash.clone()));
+
+        let mut invalid = mine_on(&b3, Vec::new(), "bob", &params);
+        invalid.hash = "00".repeat(32);
+        assert!(db.accept_block(&invalid, &params).is_err());
+    }
+
+    #[test]
+    fn single_chain_store_only_extends_its_tip() {
+        let dir = TempDir::new("file-outcomes");
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let store = FileStore::open(dir.path()).unwrap();
+        let genesis = make_genesis(&params);
+        store.accept_block(&genesis, &params).unwrap();
+        let a1 = mine_on(&genesis, Vec::new(), "alice", &params);
+        assert!(matches!(
+            store.accept_block(&a1, &params).unwrap(),
+            AcceptOutcome::Extended
+        ));
+        assert!(matches!(
+            store.accept_block(&a1, &params).unwrap(),
+            AcceptOutcome::AlreadyKnown
+        ));
+        let b1 = mine_on(&genesis, Vec::new(), "bob", &params);
+        assert!(store.accept_block(&b1, &params).is_err());
+    }
+
+    #[test]
+    fn dust_threshold_boundaries() {
+        let params = ChainParams {
+            difficulty: 4,
+            dust_threshold: 5,
+            dust_is_consensus: true,
+            ..ChainParams::default()
+        };
+        assert_eq!(check_tx_dust(&tx("a", "b", 5), &params), Ok(()));
+        assert_eq!(
+            check_tx_dust(&tx("a", "b", 4), &params),
+            Err(ValidationError::DustOutput {
+                index: None,
+                amount: 4,
+                threshold: 5
+            })
+        );
+
+        let genesis = make_genesis(&params);
+        let mut block = mine_on(&genesis, vec![tx("a", "b", 5)], "miner", &params);
+        assert_eq!(check_dust(&block, &params), Ok(()));
+        block.txs.push(tx("a", "b", 4));
+        assert_eq!(
+            check_dust(&block, &params),
+            Err(ValidationError::DustOutput {
+                index: Some(1),
+                amount: 4,
+                threshold: 5
+            })
+        );
+        // policy-only dust never invalidates a block, but the pool refuses it
+        let policy = ChainParams {
+            dust_is_consensus: false,
+            ..params.clone()
+        };
+        assert_eq!(check_dust(&block, &policy), Ok(()));
+        let mut pool = Mempool::new(None);
+        assert!(pool.add(tx("a", "b", 4), 0, &policy).is_err());
+        pool.add(tx("a", "b", 5), 0, &policy).unwrap();
+    }
+
+    /// Accepts empty blocks on `parent` at the given timestamps.
+    fn extend_at(db: &dyn Store, parent: &Block, timestamps: &[i64], params: &ChainParams) {
+        let mut parent = parent.clone();
+        for &timestamp in timestamps {
+            let header = BlockHeader {
+                version: 0,
+                parent_hash: parent.hash.clone(),
+                merkle_root: String::new(),
+                timestamp,
+                nonce: 0,
+                difficulty: params.difficulty,
+                miner: addr("miner"),
+            };
+            parent = mine_block(&header, &[]);
+            db.accept_block(&parent, params).unwrap();
+        }
+    }
+
+    #[test]
+    fn interval_stats_by_hand() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, genesis) = chain("intervals", &params);
+        let t = genesis.header.timestamp;
+        // intervals 10, 20, 30
+        extend_at(&db, &genesis, &[t + 10, t + 30, t + 60], &params);
+
+        let stats = db.block_interval_stats(4).unwrap();
+        assert_eq!(stats.count, 3);
+        assert_eq!((stats.min, stats.max), (10, 30));
+        assert!((stats.mean - 20.0).abs() < 1e-9);
+        assert!((stats.variance - 200.0 / 3.0).abs() < 1e-9);
+        assert!((stats.std_dev - (200.0f64 / 3.0).sqrt()).abs() < 1e-9);
+
+        // the newest two intervals only; a larger window stops at genesis
+        let stats = db.block_interval_stats(3).unwrap();
+        assert_eq!(stats.count, 2);
+        assert!((stats.mean - 25.0).abs() < 1e-9);
+        assert!((stats.variance - 25.0).abs() < 1e-9);
+        assert_eq!(db.block_interval_stats(100).unwrap().count, 3);
+        assert!(db.block_interval_stats(1).is_err());
+    }
+
+    #[test]
+    fn audit_log_records_blocks_and_reorgs() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let dir = TempDir::new("audit");
+        let log_path = dir.path().join("audit.log");
+        let db = ChainDB::open(dir.path().join("db").to_str().unwrap())
+            .unwrap()
+            .with_audit_log(AuditLog::open(&log_path).unwrap());
+        let genesis = make_genesis(&params);
+        db.accept_block(&genesis, &params).unwrap();
+        let a1 = mine_on(&genesis, vec![tx("alice", "bob", 2)], "alice", &params);
+        db.accept_block(&a1, &params).unwrap();
+        let side = extend(&db, &genesis, 2, "bob", &params);
+        db.rollback_to(1).unwrap();
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        let t = genesis.header.timestamp;
+        assert_eq!(
+            lines,
+            [
+                format!("block 0 {} {} 0", genesis.hash, t),
+                format!("block 1 {} {} 1", a1.hash, t + 10),
+                format!("reorg 0 1 {}", a1.hash),
+                format!("block 1 {} {} 0", side[0].hash, t + 10),
+                format!("block 2 {} {} 0", side[1].hash, t + 20),
+                format!("reorg 1 1 {}", side[1].hash),
+            ]
+        );
+    }
+
+    /// The transaction tree as first written, over hex strings throughout.
+    fn string_merkle_root(txs: &[Transaction]) -> String {
+        let mut level: Vec<String> = txs.iter().map(Transaction::txid).collect();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => {
+                        let mut h = Sha256::new();
+                        h.update(left.as_bytes());
+                        h.update(right.as_bytes());
+                        hex::encode(h.finalize())
+                    }
+                    [odd] => odd.clone(),
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        level.pop().unwrap_or_default()
+    }
+
+    #[test]
+    fn merkle_roots_are_unchanged() {
+        let txs: Vec<Transaction> = (1..=5).map(|n| tx("alice", "bob", n)).collect();
+        for n in 0..=txs.len() {
+            assert_eq!(
+                merkle_root(&txs[..n]),
+                string_merkle_root(&txs[..n]),
+                "{}",
+                n
+            );
+        }
+        assert_eq!(merkle_root(&[]), "");
+        assert_eq!(merkle_root(&txs[..1]), txs[0].txid());
+
+        assert!(merkle_root_of(&[]).is_empty());
+        assert_eq!(merkle_root_of(&[vec![7; 32]]), vec![7; 32]);
+        // an empty leaf is hashed like any other
+        let root = merkle_root_of(&[Vec::new(), vec![1]]);
+        assert_eq!(root, Sha256::digest(b"01").to_vec());
+    }
+
+    #[test]
+    fn address_validation_and_serde() {
+        for bad in [
+            "",
+            "has space",
+            "dash-ed",
+            "ünicode",
+            &"a".repeat(Address::MAX_LEN + 1),
+        ] {
+            assert!(Address::parse(bad).is_err(), "{:?}", bad);
+            let json = serde_json::to_string(bad).unwrap();
+            assert!(serde_json::from_str::<Address>(&json).is_err(), "{:?}", bad);
+        }
+        for good in ["a", "miner_1", "Z9", &"a".repeat(Address::MAX_LEN)] {
+            let address: Address = good.parse().unwrap();
+            assert_eq!(address.as_str(), good);
+            assert_eq!(address.to_string(), good);
+            // serialized as the plain string
+            let json = serde_json::to_string(&address).unwrap();
+            assert_eq!(json, format!("{:?}", good));
+            assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), address);
+        }
+
+        let block = r#"{"header":{"parent_hash":"","merkle_root":"","timestamp":0,"nonce":0,"difficulty":0,"miner":"bad miner"},"txs":[],"hash":""}"#;
+        assert!(decode_block(block.as_bytes()).is_err());
+        assert!(decode_block(block.replace("bad miner", "miner").as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn prune_orphans_removes_old_side_branches() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, genesis) = chain("prune", &params);
+        let best = extend(&db, &genesis, 4, "miner", &params);
+        let old_side = extend(&db, &genesis, 2, "old", &params);
+        let recent_side = extend(&db, &best[1], 1, "recent", &params);
+
+        // the old branch starts at height 1 and takes its child with it
+        assert_eq!(db.prune_orphans(2).unwrap(), 2);
+        for gone in &old_side {
+            assert!(db.get_block(&gone.hash).unwrap().is_none());
+        }
+        assert!(db.get_block(&recent_side[0].hash).unwrap().is_some());
+        for kept in &best {
+            assert!(db.get_block(&kept.hash).unwrap().is_some());
+        }
+        let report = db.verify_indexes().unwrap();
+        assert!(report.is_consistent(), "{}", report);
+
+        assert_eq!(db.prune_orphans(u64::MAX).unwrap(), 1);
+        assert_eq!(db.prune_orphans(u64::MAX).unwrap(), 0);
+        assert_eq!(db.verify_chain().unwrap(), 4);
+    }
+
+    #[test]
+    fn version_bits_are_counted_over_the_window() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, genesis) = chain("version-bits", &params);
+        let mut parent = genesis;
+        // oldest first: bit 0 on every block, bit 3 on the last two
+        for version in [0b0001, 0b0001, 0b1001, 0b1001] {
+            let header = BlockHeader {
+                version,
+                parent_hash: parent.hash.clone(),
+                merkle_root: String::new(),
+                timestamp: parent.header.timestamp + 10,
+                nonce: 0,
+                difficulty: params.difficulty,
+                miner: addr("miner"),
+            };
+            parent = mine_block(&header, &[]);
+            db.accept_block(&parent, &params).unwrap();
+        }
+        let counts = db.version_bit_statistics(3).unwrap();
+        assert_eq!((counts[0], counts[3]), (3, 2));
+        assert_eq!(counts.iter().sum::<u64>(), 5);
+        // genesis sets no bits, so a window past it counts the same blocks
+        let counts = db.version_bit_statistics(100).unwrap();
+        assert_eq!((counts[0], counts[3]), (4, 2));
+        assert_eq!(db.version_bit_statistics(0).unwrap(), [0; 32]);
+    }
+
+    #[test]
+    fn balance_diff_of_known_transfers() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, genesis) = chain("balance-diff", &params);
+        let first = mine_on(&genesis, vec![tx("alice", "bob", 10)], "miner", &params);
+        db.accept_block(&first, &params).unwrap();
+        let txs = vec![tx("bob", "carol", 4), tx("alice", "alice", 7)];
+        db.accept_block(&mine_on(&first, txs, "miner", &params), &params)
+            .unwrap();
+
+        assert_eq!(
+            db.balance_diff_between(0, 2).unwrap(),
+            [(addr("alice"), -10), (addr("bob"), 6), (addr("carol"), 4)]
+        );
+        assert_eq!(
+            db.balance_diff_between(1, 2).unwrap(),
+            [(addr("alice"), 0), (addr("bob"), -4), (addr("carol"), 4)]
+        );
+        assert!(db.balance_diff_between(2, 2).unwrap().is_empty());
+        assert!(db.balance_diff_between(2, 1).is_err());
+    }
+
+    #[test]
+    fn rollback_leaves_a_consistent_chain() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, genesis) = chain("rollback", &params);
+        let blocks = extend(&db, &genesis, 4, "miner", &params);
+        let side = mine_on(&blocks[0], Vec::new(), "side", &params);
+        assert!(matches!(
+            db.accept_block(&side, &params).unwrap(),
+            AcceptOutcome::SideBranch
+        ));
+
+        // three best-chain blocks and the side block above height 1
+        assert_eq!(db.rollback_to(1).unwrap(), 4);
+        assert_eq!(db.tip_height().unwrap(), Some(1));
+        assert_eq!(db.latest_hash().unwrap(), Some(blocks[0].hash.clone()));
+        for gone in blocks[1..].iter().chain([&side]) {
+            assert!(db.get_block(&gone.hash).unwrap().is_none());
+            assert_eq!(db.height_of(&gone.hash).unwrap(), None);
+            assert_eq!(db.stored_work(&gone.hash).unwrap(), None);
+        }
+        assert_eq!(db.hash_at_height(2).unwrap(), None);
+        assert!(db.children(&blocks[0].hash).unwrap().is_empty());
+        assert_eq!(db.miner_block_count(&addr("miner")).unwrap(), 1);
+        assert_eq!(db.miner_block_count(&addr("side")).unwrap(), 0);
+        let report = db.verify_indexes().unwrap();
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(db.verify_chain().unwrap(), 1);
+
+        // and the chain grows again from the new tip
+        let regrown = extend(&db, &blocks[0], 1, "miner", &params);
+        assert_eq!(db.height_of(&regrown[0].hash).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn file_store_rollback_rewrites_the_index() {
+        let dir = TempDir::new("file-rollback");
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let store = FileStore::open(dir.path()).unwrap();
+        let genesis = make_genesis(&params);
+        store.accept_block(&genesis, &params).unwrap();
+        let blocks = extend(&store, &genesis, 3, "miner", &params);
+        assert_eq!(store.rollback_to(1).unwrap(), 2);
+        assert!(store.rollback_to(2).is_err());
+        drop(store);
+
+        let store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.latest_hash().unwrap(), Some(blocks[0].hash.clone()));
+        assert!(store.get_block(&blocks[1].hash).unwrap().is_none());
+        assert!(!dir.path().join("blocks").join("2.bin").exists());
+        assert_eq!(store.verify_chain().unwrap(), 1);
+    }
+
+    /// A serialized block on genesis carrying `count` copies of one
+    /// transaction.
+    fn block_json_with_txs(count: usize) -> Vec<u8> {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let mut block = mine_on(&make_genesis(&params), Vec::new(), "miner", &params);
+        block.txs = vec![tx("alice", "bob", 1); count];
+        serde_json::to_vec(&block).unwrap()
+    }
+
+    #[test]
+    fn decode_block_refuses_absurd_tx_counts() {
+        let block = decode_block(&block_json_with_txs(MAX_BLOCK_TXS)).unwrap();
+        assert_eq!(block.txs.len(), MAX_BLOCK_TXS);
+        let err = decode_block(&block_json_with_txs(MAX_BLOCK_TXS + 1)).unwrap_err();
+        assert!(err.to_string().contains("at most"), "{:#}", err);
+
+        let oversized = vec![b' '; MAX_BLOCK_BYTES + 1];
+        let err = decode_block(&oversized).unwrap_err();
+        assert!(err.to_string().contains("limit"), "{:#}", err);
+    }
+
+    #[test]
+    fn concurrent_acceptance_keeps_the_chain_consistent() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, _genesis) = chain("concurrent", &params);
+        std::thread::scope(|scope| {
+            for id in 0..4 {
+                let (db, params) = (&db, &params);
+                scope.spawn(move || {
+                    let miner = format!("miner_{}", id);
+                    for _ in 0..5 {
+                        // racing miners build on whatever the tip is now
+                        let parent = db.get_latest().unwrap().unwrap();
+                        let block = mine_on(&parent, Vec::new(), &miner, params);
+                        db.accept_block(&block, params).unwrap();
+                        assert!(db.verify_chain().is_ok());
+                    }
+                });
+            }
+        });
+
+        let report = db.verify_indexes().unwrap();
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.blocks_checked, 21);
+        let tip_height = db.verify_chain().unwrap();
+        let mined: u64 = db.miner_block_counts().unwrap().iter().map(|c| c.1).sum();
+        assert_eq!(mined, tip_height);
+    }
+
+    #[test]
+    fn ancestry_across_a_fork() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let (_dir, db, genesis) = chain("ancestry", &params);
+        let best = extend(&db, &genesis, 3, "miner", &params);
+        let side = extend(&db, &best[0], 1, "side", &params).remove(0);
+        let hashes =
+            |blocks: &[&Block]| -> Vec<String> { blocks.iter().map(|b| b.hash.clone()).collect() };
+
+        assert!(db.ancestors(&genesis.hash, 5).unwrap().is_empty());
+        assert_eq!(
+            db.ancestors(&best[2].hash, 2).unwrap(),
+            hashes(&[&best[1], &best[0]])
+        );
+        assert_eq!(
+            db.ancestors(&best[2].hash, 100).unwrap(),
+            hashes(&[&best[1], &best[0], &genesis])
+        );
+        assert_eq!(
+            db.ancestors(&side.hash, 100).unwrap(),
+            hashes(&[&best[0], &genesis])
+        );
+        assert!(db.ancestors("missing", 1).is_err());
+
+        assert!(db.is_ancestor(&genesis.hash, &best[2].hash).unwrap());
+        assert!(db.is_ancestor(&genesis.hash, &side.hash).unwrap());
+        assert!(db.is_ancestor(&best[0].hash, &side.hash).unwrap());
+        assert!(db.is_ancestor(&side.hash, &side.hash).unwrap());
+        assert!(!db.is_ancestor(&best[1].hash, &side.hash).unwrap());
+        assert!(!db.is_ancestor(&side.hash, &best[2].hash).unwrap());
+        assert!(!db.is_ancestor(&best[2].hash, &genesis.hash).unwrap());
+
+        // the walk reads headers, not the blocks themselves
+        db.db.remove(best[0].hash.as_bytes()).unwrap();
+        assert_eq!(
+            db.ancestors(&best[2].hash, 100).unwrap(),
+            hashes(&[&best[1], &best[0], &genesis])
+        );
+    }
+
+    #[test]
+    fn regtest_blocks_are_mined_on_the_first_attempt() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("regtest-mining", &params);
+        assert!(requires_no_work(params.difficulty));
+        // any hash meets difficulty 0, so the first nonce mine_block tries wins
+        assert!(meets_difficulty(&"f".repeat(64), params.difficulty));
+        let block = mine_on(&genesis, Vec::new(), "miner", &params);
+        assert!(matches!(
+            db.accept_block(&block, &params).unwrap(),
+            AcceptOutcome::Extended
+        ));
+
+        let no_pow_elsewhere = ChainParams {
+            difficulty: 0,
+            ..ChainParams::default()
+        };
+        assert_eq!(
+            check_difficulty(&block, &no_pow_elsewhere),
+            Err(ValidationError::NoPowNotAllowed { difficulty: 0 })
+        );
+    }
+
+    #[test]
+    fn reorged_out_transactions_return_to_the_pool() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("reorg-pool", &params);
+        let (orphaned, shared, pending) = (tx("a", "b", 1), tx("c", "d", 2), tx("e", "f", 3));
+        let old = mine_on(
+            &genesis,
+            vec![orphaned.clone(), shared.clone()],
+            "old",
+            &params,
+        );
+        assert_eq!(
+            Mempool::new(None).reconstruct_after_reorg(
+                &db.accept_block(&old, &params).unwrap(),
+                1,
+                &params
+            ),
+            0
+        );
+
+        let mut pool = Mempool::new(None);
+        pool.add(pending.clone(), 1, &params).unwrap();
+        let new = mine_on(&genesis, vec![shared, pending], "new", &params);
+        db.accept_block(&new, &params).unwrap();
+        let tip = mine_on(&new, Vec::new(), "new", &params);
+        let outcome = db.accept_block(&tip, &params).unwrap();
+
+        // only the transaction the new branch left out comes back, and the
+        // pending one it mined is dropped
+        assert_eq!(pool.reconstruct_after_reorg(&outcome, 2, &params), 1);
+        assert_eq!(pool.txs(), [orphaned]);
+        assert_eq!(pool.entries()[0].first_seen_height, 2);
+    }
+
+    #[test]
+    fn network_presets_are_distinct() {
+        let presets: Vec<ChainParams> = ["mainnet", "testnet", "regtest"]
+            .iter()
+            .map(|name| ChainParams::for_network(name.parse().unwrap()))
+            .collect();
+        for (i, a) in presets.iter().enumerate() {
+            for b in &presets[i + 1..] {
+                assert_ne!(make_genesis(a).hash, make_genesis(b).hash);
+                assert_ne!(a.magic, b.magic);
+                assert_ne!(a.difficulty, b.difficulty);
+            }
+            assert!(standalone_checks(&make_genesis(a), a)
+                .iter()
+                .all(|c| c.1.is_ok()));
+        }
+        assert_eq!(
+            make_genesis(&presets[0]).hash,
+            make_genesis(&ChainParams::default()).hash
+        );
+        assert!(presets[2].allow_no_pow && !presets[0].allow_no_pow && !presets[1].allow_no_pow);
+        assert!("devnet".parse::<Network>().is_err());
+        assert_ne!(Network::Mainnet.data_dir(), Network::Regtest.data_dir());
+    }
+
+    #[test]
+    fn txid_is_stable() {
+        let t = tx("alice", "bob", 42);
+        let json = br#"{"from":"alice","to":"bob","amount":42}"#;
+        assert_eq!(t.txid(), hex::encode(Sha256::digest(json)));
+        // decoding drops unknown fields, so they cannot change the id
+        let extended = br#"{"from":"alice","to":"bob","amount":42,"memo":"x"}"#;
+        assert_eq!(
+            decode_tx(extended, Decoding::Lenient).unwrap().txid(),
+            t.txid()
+        );
+        assert_eq!(t.clone().txid(), t.txid());
+        for other in [
+            tx("alice", "bob", 43),
+            tx("bob", "alice", 42),
+            tx("alice", "carol", 42),
+        ] {
+            assert_ne!(other.txid(), t.txid());
+        }
+    }
+
+    #[test]
+    fn genesis_template_parses_back_to_the_same_genesis() {
+        for params in [ChainParams::mainnet(), ChainParams::testnet()] {
+            let spec = GenesisSpec::template(&params, 1_700_000_000);
+            let json = serde_json::to_vec(&spec).unwrap();
+            let mut applied = ChainParams::regtest();
+            GenesisSpec::from_json(&json)
+                .unwrap()
+                .apply(&mut applied)
+                .unwrap();
+            let expected = ChainParams {
+                genesis_timestamp: 1_700_000_000,
+                ..params
+            };
+            assert_eq!(make_genesis(&applied).hash, make_genesis(&expected).hash);
+            assert_eq!(applied.magic, expected.magic);
+            assert_eq!(applied.difficulty, expected.difficulty);
+        }
+    }
+
+    #[test]
+    fn rollback_stops_at_the_latest_checkpoint() {
+        let params = ChainParams::regtest();
+        let dir = TempDir::new("rollback-checkpoint");
+        let db = ChainDB::open(dir.path().join("db"))
+            .unwrap()
+            .with_auto_checkpoints(2);
+        let genesis = make_genesis(&params);
+        db.accept_block(&genesis, &params).unwrap();
+        let blocks = extend(&db, &genesis, 5, "miner", &params);
+        let checkpoints: Vec<u64> = db.checkpoints().unwrap().iter().map(|c| c.0).collect();
+        assert_eq!(checkpoints, [2, 4]);
+
+        let err = db.rollback_to(1).unwrap_err();
+        assert!(err.to_string().contains("checkpoint"), "{:#}", err);
+        assert_eq!(db.tip_height().unwrap(), Some(5));
+        assert_eq!(db.rollback_to(4).unwrap(), 1);
+        assert_eq!(db.latest_hash().unwrap(), Some(blocks[3].hash.clone()));
+        assert_eq!(db.checkpoints().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reorgs_below_a_checkpoint_are_rejected() {
+        let params = ChainParams::regtest();
+        let dir = TempDir::new("reorg-checkpoint");
+        let db = ChainDB::open(dir.path().join("db"))
+            .unwrap()
+            .with_auto_checkpoints(2);
+        let genesis = make_genesis(&params);
+        db.accept_block(&genesis, &params).unwrap();
+        let best = extend(&db, &genesis, 5, "miner", &params);
+
+        // a side branch forked at height 1, below the checkpoint at 4; at
+        // equal work it is only stored
+        let side = extend(&db, &best[0], 4, "side", &params);
+        let tip = db.get_latest().unwrap().unwrap();
+        let checkpoints = db.checkpoints().unwrap();
+        assert_eq!(tip.hash, best[4].hash);
+
+        let heavier = mine_on(&side[3], Vec::new(), "side", &params);
+        let err = db.accept_block(&heavier, &params).unwrap_err();
+        assert!(err.to_string().contains("checkpoint"), "{:#}", err);
+        assert_eq!(db.get_latest().unwrap().unwrap().hash, tip.hash);
+        assert_eq!(db.checkpoints().unwrap(), checkpoints);
+    }
+
+    #[test]
+    fn tip_header_matches_the_latest_block() {
+        let params = ChainParams::regtest();
+        let dir = TempDir::new("tip-header");
+        let db = ChainDB::open(dir.path().join("db")).unwrap();
+        assert!(db.tip_header().unwrap().is_none());
+        let genesis = make_genesis(&params);
+        db.accept_block(&genesis, &params).unwrap();
+        for _ in 0..3 {
+            let tip = db.get_latest().unwrap().unwrap();
+            assert_eq!(
+                serde_json::to_value(db.tip_header().unwrap().unwrap()).unwrap(),
+                serde_json::to_value(&tip.header).unwrap()
+            );
+            extend(&db, &tip, 1, "miner", &params);
+        }
+        let tip = db.get_latest().unwrap().unwrap();
+        assert_eq!(
+            hash_block_header(&db.tip_header().unwrap().unwrap()),
+            tip.hash
+        );
+    }
+
+    #[test]
+    fn forward_walk_is_the_reversed_backward_walk() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("walk", &params);
+        let best = extend(&db, &genesis, 4, "miner", &params);
+        extend(&db, &best[0], 2, "side", &params);
+
+        let forward: Vec<String> = db
+            .walk_from(&genesis.hash)
+            .map(|b| b.unwrap().hash)
+            .collect();
+        let mut backward: Vec<String> = db.iter_chain().unwrap().map(|b| b.unwrap().hash).collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+        assert_eq!(forward.len(), 5);
+
+        let tail: Vec<String> = db
+            .walk_from(&best[1].hash)
+            .map(|b| b.unwrap().hash)
+            .collect();
+        assert_eq!(tail, forward[2..]);
+        let mut missing = db.walk_from("missing");
+        assert!(missing.next().unwrap().is_err());
+        assert!(missing.next().is_none());
+    }
+
+    #[test]
+    fn build_block_uses_exactly_the_given_txs() {
+        let params = ChainParams::testnet();
+        let genesis = make_genesis(&params);
+        let txs = vec![tx("bob", "carol", 2), tx("alice", "bob", 9)];
+        let build = |txs: Vec<Transaction>| {
+            build_block(&genesis, txs, addr("miner"), 0x20, 1_000, 8, &params)
+        };
+        let template = build(txs.clone()).unwrap();
+        assert_eq!(template.txs, txs);
+        let h = &template.header;
+        assert_eq!(h.parent_hash, genesis.hash);
+        assert_eq!(h.merkle_root, merkle_root(&txs));
+        assert_eq!((h.version, h.timestamp, h.difficulty), (0x20, 1_000, 8));
+        assert_eq!(
+            hash_block_header(h),
+            hash_block_header(&build(txs.clone()).unwrap().header)
+        );
+
+        let block = template.mine(NonceStride::default());
+        assert_eq!(block.txs, txs);
+        assert!(failed_checks(&block, &params).is_empty());
+
+        let consensus_dust = ChainParams {
+            dust_threshold: 5,
+            dust_is_consensus: true,
+            ..params.clone()
+        };
+        assert!(build_block(
+            &genesis,
+            txs.clone(),
+            addr("miner"),
+            0,
+            1_000,
+            8,
+            &consensus_dust
+        )
+        .is_err());
+        assert!(build(vec![tx("a", "b", 1); MAX_BLOCK_TXS + 1]).is_err());
+    }
+
+    /// A regtest node for tests: a scratch store, params and a mempool.
+    /// Transactions are unsigned and there is no issuance, so there is no
+    /// funding step and sends name the sender directly.
+    struct TestNode {
+        _dir: TempDir,
+        db: ChainDB,
+        params: ChainParams,
+        pool: Mempool,
+    }
+
+    impl TestNode {
+        fn new(name: &str) -> Self {
+            let params = ChainParams::regtest();
+            let (dir, db, _genesis) = chain(name, &params);
+            Self {
+                _dir: dir,
+                db,
+                params,
+                pool: Mempool::new(None),
+            }
+        }
+
+        fn height(&self) -> u64 {
+            self.db.tip_height().unwrap().unwrap()
+        }
+
+        fn send(&mut self, from: &str, to: &str, amount: u64) -> Result<()> {
+            let height = self.height();
+            self.pool.add(tx(from, to, amount), height, &self.params)
+        }
+
+        /// Mines the whole mempool into a block on the tip.
+        fn mine(&mut self) -> Block {
+            let parent = self.db.get_latest().unwrap().unwrap();
+            let block = build_block(
+                &parent,
+                self.pool.txs(),
+                addr("miner"),
+                0,
+                parent.header.timestamp + 10,
+                self.params.difficulty,
+                &self.params,
+            )
+            .unwrap()
+            .mine(NonceStride::default());
+            self.db.accept_block(&block, &self.params).unwrap();
+            self.pool.remove_included(&block);
+            block
+        }
+
+        /// Net amount `who` has received over the best chain.
+        fn balance(&self, who: &str) -> i128 {
+            let diff = self.db.balance_diff_between(0, self.height()).unwrap();
+            diff.iter()
+                .find(|(a, _)| *a == addr(who))
+                .map_or(0, |(_, delta)| *delta)
+        }
+    }
+
+    #[test]
+    fn test_node_sends_mines_and_reports_balances() {
+        let mut node = TestNode::new("test-node");
+        node.send("alice", "bob", 30).unwrap();
+        node.send("bob", "carol", 10).unwrap();
+        assert!(node.send("alice", "bob", 0).is_err());
+        let block = node.mine();
+        assert_eq!(block.txs.len(), 2);
+        assert!(node.pool.txs().is_empty());
+
+        node.send("carol", "alice", 4).unwrap();
+        node.mine();
+        node.mine();
+        assert_eq!(node.height(), 3);
+        assert_eq!(node.balance("alice"), -26);
+        assert_eq!(node.balance("bob"), 20);
+        assert_eq!(node.balance("carol"), 6);
+        assert_eq!(node.balance("dave"), 0);
+    }
+
+    /// `prefix` padded to a full 64-digit hash with `f`s.
+    fn hash_with(prefix: &str) -> String {
+        format!("{:f<64}", prefix)
+    }
+
+    #[test]
+    fn difficulty_vectors() {
+        let zero = "0".repeat(64);
+        let cases = [
+            // (hash, difficulty, meets)
+            (hash_with("000"), 12, true),
+            (hash_with("000"), 11, true),
+            // work is counted in whole nibbles, so 13..=15 round down to 12
+            (hash_with("000"), 13, true),
+            (hash_with("000"), 16, false),
+            (hash_with(""), 0, true),
+            (hash_with(""), 3, true),
+            (hash_with(""), 4, false),
+            (zero.clone(), MAX_DIFFICULTY, true),
+            (format!("{}1", &zero[1..]), MAX_DIFFICULTY, false),
+        ];
+        for (hash, difficulty, meets) in &cases {
+            assert_eq!(
+                meets_difficulty(hash, *difficulty),
+                *meets,
+                "{} at {}",
+                hash,
+                difficulty
+            );
+        }
+
+        for (difficulty, free) in [(0, true), (3, true), (4, false), (MAX_DIFFICULTY, false)] {
+            assert_eq!(requires_no_work(difficulty), free, "{}", difficulty);
+        }
+
+        // a hash meets the difficulty it achieves but not the next nibble
+        for hash in [hash_with("000"), hash_with("07"), hash_with(""), zero] {
+            let achieved = hash_to_difficulty(&hash);
+            assert!(meets_difficulty(&hash, achieved), "{}", hash);
+            if achieved < MAX_DIFFICULTY {
+                assert!(!meets_difficulty(&hash, (achieved / 4 + 1) * 4), "{}", hash);
+            }
+        }
+    }
+
+    #[test]
+    fn size_estimates_cover_every_keyspace() {
+        let params = ChainParams::regtest();
+        let dir = TempDir::new("sizes");
+        let db = ChainDB::open(dir.path().join("db"))
+            .unwrap()
+            .with_auto_checkpoints(2);
+        let genesis = make_genesis(&params);
+        db.accept_block(&genesis, &params).unwrap();
+        extend(&db, &genesis, 3, "miner", &params);
+        db.db.flush().unwrap();
+
+        let sizes = db.size_estimates().unwrap();
+        let names: Vec<&str> = sizes.iter().map(|s| s.0).collect();
+        assert_eq!(
+            names,
+            [
+                "blockheight",
+                "blocks",
+                "checkpoint",
+                "children",
+                "header",
+                "height",
+                "meta",
+                "miner_stats",
+                "work"
+            ]
+        );
+        assert!(sizes.iter().all(|s| s.1 > 0), "{:?}", sizes);
+        let total: u64 = sizes.iter().map(|s| s.1).sum();
+        assert!(total <= db.size_on_disk().unwrap());
+    }
+
+    #[test]
+    fn verify_indexes_reports_a_corrupted_height_index() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("verify-indexes", &params);
+        let best = extend(&db, &genesis, 3, "miner", &params);
+        let report = db.verify_indexes().unwrap();
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.blocks_checked, 4);
+
+        // height 2 now names height 1's block, and height 9 is invented
+        db.db
+            .insert(height_key(2), best[0].hash.as_bytes())
+            .unwrap();
+        db.db
+            .insert(height_key(9), best[0].hash.as_bytes())
+            .unwrap();
+        let report = db.verify_indexes().unwrap();
+        assert_eq!(
+            report.discrepancies,
+            [
+                (
+                    "height",
+                    format!("2 holds {}, expected {}", best[0].hash, best[1].hash)
+                ),
+                ("height", "9 is above the tip".to_string()),
+            ]
+        );
+
+        assert_eq!(db.reindex().unwrap(), 4);
+        assert!(db.verify_indexes().unwrap().is_consistent());
+        assert_eq!(db.hash_at_height(2).unwrap(), Some(best[1].hash.clone()));
+    }
+
+    #[test]
+    fn nonce_strides_are_disjoint() {
+        let (even, odd) = (
+            NonceStride::new(0, 2).unwrap(),
+            NonceStride::new(1, 2).unwrap(),
+        );
+        assert_eq!(even.slots(), odd.slots());
+        let last = even.slots() - 1;
+        let evens: HashSet<u64> = (0..1000).chain([last]).map(|k| even.nonce(k)).collect();
+        let odds: HashSet<u64> = (0..1000).chain([last]).map(|k| odd.nonce(k)).collect();
+        assert!(evens.is_disjoint(&odds));
+        assert!(evens.iter().all(|n| n % 2 == 0) && odds.iter().all(|n| n % 2 == 1));
+        // the last slot stops short of wrapping
+        assert_eq!(odd.nonce(last), u64::MAX - 2);
+
+        assert!(NonceStride::new(2, 2).is_err());
+        assert_eq!(NonceStride::default(), NonceStride::new(0, 1).unwrap());
+        // miners sharing a template find different blocks
+        let params = ChainParams::testnet();
+        let template = build_block(
+            &make_genesis(&params),
+            Vec::new(),
+            addr("m"),
+            0,
+            1,
+            0,
+            &params,
+        )
+        .unwrap();
+        assert_eq!(template.mine(even).header.nonce % 2, 0);
+        assert_eq!(template.mine(odd).header.nonce % 2, 1);
+    }
+
+    #[test]
+    fn max_ancestors_limits_pending_txs_per_sender() {
+        let params = ChainParams::regtest();
+        let mut pool = Mempool::new(None).with_max_ancestors(2);
+        pool.add(tx("alice", "bob", 1), 0, &params).unwrap();
+        pool.add(tx("alice", "bob", 2), 0, &params).unwrap();
+        let err = pool.add(tx("alice", "bob", 3), 0, &params).unwrap_err();
+        assert!(err.to_string().contains("limit is 2"), "{:#}", err);
+        pool.add(tx("bob", "alice", 1), 0, &params).unwrap();
+
+        // mining one frees a slot
+        let genesis = make_genesis(&params);
+        let block = mine_on(&genesis, vec![tx("alice", "bob", 1)], "miner", &params);
+        pool.remove_included(&block);
+        pool.add(tx("alice", "bob", 3), 1, &params).unwrap();
+        assert_eq!(pool.len(), 3);
+    }
+
+    #[test]
+    fn strict_decoding_rejects_unknown_fields() {
+        let params = ChainParams::regtest();
+        let block = mine_on(
+            &make_genesis(&params),
+            vec![tx("a", "b", 1)],
+            "miner",
+            &params,
+        );
+        let value = serde_json::to_value(&block).unwrap();
+        let with_extra = |pointer: &str| {
+            let mut value = value.clone();
+            value
+                .pointer_mut(pointer)
+                .unwrap()
+                .as_object_mut()
+                .unwrap()
+                .insert("future".into(), 1.into());
+            serde_json::to_vec(&value).unwrap()
+        };
+        let plain = serde_json::to_vec(&value).unwrap();
+        assert!(decode_block_with(&plain, Decoding::Strict).is_ok());
+        for (pointer, place) in [("", "block"), ("/header", "header"), ("/txs/0", "tx 0")] {
+            let bytes = with_extra(pointer);
+            let err = decode_block_with(&bytes, Decoding::Strict).unwrap_err();
+            assert!(err.to_string().contains(place), "{}: {:#}", place, err);
+            let lenient = decode_block_with(&bytes, Decoding::Lenient).unwrap();
+            assert_eq!(lenient.hash, block.hash);
+            assert_eq!(hash_block_header(&lenient.header), block.hash);
+        }
+        assert_eq!("strict".parse::<Decoding>().unwrap(), Decoding::Strict);
+        assert!("loose".parse::<Decoding>().is_err());
+    }
+
+    #[test]
+    fn jittered_stays_within_bounds() {
+        let base = Duration::from_millis(1_000);
+        let jitter = Duration::from_millis(250);
+        for _ in 0..1_000 {
+            let d = jittered(base, jitter);
+            assert!(d >= base - jitter && d <= base + jitter, "{:?}", d);
+        }
+        assert_eq!(jittered(base, Duration::ZERO), base);
+        // never below zero
+        for _ in 0..100 {
+            assert!(jittered(Duration::from_millis(10), jitter) <= Duration::from_millis(260));
+        }
+    }
+
+    #[test]
+    fn jittered_survives_huge_jitter() {
+        let cap = Duration::from_millis(u64::MAX / 2);
+        for base in [Duration::ZERO, Duration::from_secs(1), Duration::MAX] {
+            for jitter in [cap, cap + Duration::from_millis(1), Duration::MAX] {
+                let d = jittered(base, jitter);
+                assert!(d >= base.saturating_sub(cap), "{:?} {:?}", base, jitter);
+                assert!(d <= base.saturating_add(cap), "{:?} {:?}", base, jitter);
+            }
+        }
+    }
+
+    #[test]
+    fn velocity_limit_caps_each_sender_per_window() {
+        let params = ChainParams::regtest();
+        let mut pool = Mempool::new(None).with_velocity_limit("100/10".parse().unwrap());
+        pool.add(tx("alice", "bob", 60), 0, &params).unwrap();
+        assert!(pool.add(tx("alice", "bob", 50), 5, &params).is_err());
+        pool.add(tx("carol", "bob", 100), 5, &params).unwrap();
+        // the first spend has left the window
+        pool.add(tx("alice", "bob", 50), 10, &params).unwrap();
+        assert_eq!(pool.len(), 3);
+    }
+
+    #[test]
+    fn block_meta_agrees_with_the_chain() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("block-meta", &params);
+        let best = extend(&db, &genesis, 3, "miner", &params);
+        let side = extend(&db, &best[0], 1, "side", &params).remove(0);
+        let meta = |block: &Block| db.get_block_meta(&block.hash).unwrap().unwrap();
+
+        let tip = meta(&best[2]);
+        assert_eq!(
+            (tip.height, tip.on_best_chain, tip.confirmations),
+            (3, true, 1)
+        );
+        assert_eq!(tip.timestamp, best[2].header.timestamp);
+        assert_eq!(tip.difficulty, best[2].header.difficulty);
+        assert_eq!(tip.tx_count, best[2].txs.len());
+        assert_eq!(
+            tip.size,
+            db.db.get(best[2].hash.as_bytes()).unwrap().unwrap().len()
+        );
+        assert_eq!(meta(&best[0]).confirmations, 3);
+        assert_eq!(
+            (meta(&genesis).height, meta(&genesis).confirmations),
+            (0, 4)
+        );
+        assert!(meta(&best[1]).work < tip.work);
+
+        let orphan = meta(&side);
+        assert_eq!(orphan.height, 2);
+        assert!(!orphan.on_best_chain);
+        assert_eq!(orphan.confirmations, 0);
+        assert_eq!(orphan.work, meta(&best[1]).work);
+
+        assert!(db.get_block_meta("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn difficulty_is_clamped_to_the_network_range() {
+        let params = ChainParams::testnet();
+        assert_eq!(params.clamp_difficulty(0), params.min_difficulty);
+        assert_eq!(params.clamp_difficulty(4), 4);
+        assert_eq!(params.clamp_difficulty(8), 8);
+        assert_eq!(params.clamp_difficulty(MAX_DIFFICULTY), MAX_DIFFICULTY);
+        assert_eq!(params.clamp_difficulty(u32::MAX), MAX_DIFFICULTY);
+        assert_eq!(ChainParams::regtest().clamp_difficulty(0), 0);
+
+        // a block beyond the ceiling is refused before any other check
+        let mut block = mine_on(&make_genesis(&params), Vec::new(), "miner", &params);
+        block.header.difficulty = MAX_DIFFICULTY + 4;
+        assert_eq!(
+            check_difficulty(&block, &params),
+            Err(ValidationError::DifficultyOutOfRange {
+                difficulty: MAX_DIFFICULTY + 4,
+                min: 4,
+                max: MAX_DIFFICULTY
+            })
+        );
+
+        let spec = GenesisSpec {
+            block_difficulty: Some(2),
+            ..GenesisSpec::template(&params, 0)
+        };
+        assert!(spec.apply(&mut params.clone()).is_err());
+
+        // a retarget cannot step past either bound
+        let epoch = EpochRetarget { interval: 4 };
+        let slow = headers_at(&[0, 1_000, 2_000, 3_000], 5);
+        assert_eq!(epoch.next_difficulty(&slow, &params), params.min_difficulty);
+        let fast = headers_at(&[0, 0, 0, 0], MAX_DIFFICULTY - 1);
+        assert_eq!(epoch.next_difficulty(&fast, &params), MAX_DIFFICULTY);
+    }
+
+    #[test]
+    fn hash_to_difficulty_counts_leading_zero_bits() {
+        let cases = [
+            (hash_with("fff"), 0),
+            (hash_with("7f"), 1),
+            (hash_with("3f"), 2),
+            (hash_with("1f"), 3),
+            (hash_with("0f"), 4),
+            (hash_with("008f"), 8),
+            (hash_with("0001"), 15),
+            ("0".repeat(64), MAX_DIFFICULTY),
+            // counting stops at the first character that is not hex
+            (hash_with("00xyz"), 8),
+            (String::new(), 0),
+        ];
+        for (hash, bits) in &cases {
+            assert_eq!(hash_to_difficulty(hash), *bits, "{}", hash);
+        }
+    }
+
+    fn header_at(timestamp: i64, difficulty: u32) -> BlockHeader {
+        BlockHeader {
+            version: 0,
+            parent_hash: "ab".repeat(32),
+            merkle_root: String::new(),
+            timestamp,
+            nonce: 0,
+            difficulty,
+            miner: addr("miner"),
+        }
+    }
+
+    fn headers_at(timestamps: &[i64], difficulty: u32) -> Vec<BlockHeader> {
+        timestamps
+            .iter()
+            .map(|&t| header_at(t, difficulty))
+            .collect()
+    }
+
+    #[test]
+    fn fixed_difficulty_ignores_timestamps() {
+        let params = ChainParams::testnet();
+        let recent = headers_at(&[i64::MIN, i64::MAX], 20);
+        assert_eq!(FixedDifficulty.window(7), 0);
+        assert_eq!(FixedDifficulty.next_difficulty(&recent, &params), 8);
+    }
+
+    #[test]
+    fn epoch_retarget_follows_the_epoch_span() {
+        let params = ChainParams::testnet();
+        let epoch = EpochRetarget { interval: 4 };
+        assert_eq!(epoch.window(4), 4);
+        assert_eq!(epoch.window(5), 1);
+        assert_eq!(epoch.window(0), 1);
+        let cases: [(&[i64], u32); 6] = [
+            // on target: 3 intervals of 10s
+            (&[0, 10, 20, 30], 8),
+            // twice as fast
+            (&[0, 5, 10, 15], 9),
+            // far too fast is capped at 2 bits
+            (&[0, 1, 2, 3], 10),
+            // twice as slow
+            (&[0, 20, 40, 60], 7),
+            // mid-epoch holds the parent's difficulty
+            (&[0, 1], 8),
+            (&[], 8),
+        ];
+        for (timestamps, expected) in cases {
+            assert_eq!(
+                epoch.next_difficulty(&headers_at(timestamps, 8), &params),
+                expected,
+                "timestamps {:?}",
+                timestamps
+            );
+        }
+    }
+
+    #[test]
+    fn lwma_follows_weighted_solve_times() {
+        let params = ChainParams::testnet();
+        let lwma = Lwma { window: 3 };
+        assert_eq!(lwma.window(9), 4);
+        let cases: [(&[i64], u32); 5] = [
+            (&[0, 10, 20, 30], 8),
+            (&[0, 2, 4, 6], 9),
+            (&[0, 100, 200, 300], 7),
+            // a wild last solve is bounded to 6x target, one bit like any other
+            (&[0, 10, 20, 1_000_000], 7),
+            (&[0], 8),
+        ];
+        for (timestamps, expected) in cases {
+            assert_eq!(
+                lwma.next_difficulty(&headers_at(timestamps, 8), &params),
+                expected,
+                "timestamps {:?}",
+                timestamps
+            );
+        }
+    }
+
+    #[test]
+    fn retarget_rules_parse_from_the_command_line() {
+        assert_eq!("fixed".parse::<Retarget>().unwrap(), Retarget::Fixed);
+        assert_eq!(
+            "epoch".parse::<Retarget>().unwrap(),
+            Retarget::Epoch(EpochRetarget { interval: 16 })
+        );
+        assert_eq!(
+            "lwma:5".parse::<Retarget>().unwrap(),
+            Retarget::Lwma(Lwma { window: 5 })
+        );
+        for bad in ["fixed:4", "lwma:1", "epoch:x", "sha"] {
+            assert!(bad.parse::<Retarget>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn accept_block_enforces_the_configured_retarget() {
+        let params = ChainParams {
+            retarget: "lwma:2".parse().unwrap(),
+            ..ChainParams::regtest()
+        };
+        let (_dir, db, genesis) = chain("retarget-accept", &params);
+        let build = |parent: &Block, timestamp: i64, difficulty: u32| {
+            build_block(
+                parent,
+                Vec::new(),
+                addr("miner"),
+                0,
+                timestamp,
+                difficulty,
+                &params,
+            )
+            .unwrap()
+            .mine(NonceStride::default())
+        };
+        let on_time = build(&genesis, genesis.header.timestamp + 10, 0);
+        db.accept_block(&on_time, &params).unwrap();
+        let hasty = build(&on_time, on_time.header.timestamp + 1, 0);
+        db.accept_block(&hasty, &params).unwrap();
+
+        // solve times [10, 1] weigh the fast one double: one bit harder
+        assert_eq!(db.next_difficulty(&hasty.hash, &params).unwrap(), 1);
+        let stale = build(&hasty, hasty.header.timestamp + 10, 0);
+        let err = db.accept_block(&stale, &params).unwrap_err();
+        assert!(err.to_string().contains("requires 1"), "{:#}", err);
+        let next = build(&hasty, hasty.header.timestamp + 10, 1);
+        db.accept_block(&next, &params).unwrap();
+        assert_eq!(db.tip_height().unwrap(), Some(3));
+    }
+
+    #[test]
+    fn reverify_block_finds_a_corrupted_block() {
+        let params = ChainParams::testnet();
+        let (_dir, db, genesis) = chain("reverify", &params);
+        let block = mine_on(&genesis, vec![tx("alice", "bob", 5)], "miner", &params);
+        db.accept_block(&block, &params).unwrap();
+        assert_eq!(db.reverify_block(&block.hash).unwrap(), Ok(()));
+        assert_eq!(db.reverify_block(&genesis.hash).unwrap(), Ok(()));
+
+        let mut corrupted = block.clone();
+        corrupted.txs[0].amount = 500;
+        db.db
+            .insert(
+                block.hash.as_bytes(),
+                serde_json::to_vec(&corrupted).unwrap(),
+            )
+            .unwrap();
+        assert!(matches!(
+            db.reverify_block(&block.hash).unwrap(),
+            Err(ValidationError::MerkleMismatch { .. })
+        ));
+
+        assert!(db.reverify_block("missing").is_err());
+    }
+
+    #[test]
+    fn balance_deltas_follow_a_reorg_without_wrapping() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("balance-reorg", &params);
+        let out = mine_on(
+            &genesis,
+            vec![tx("alice", "bob", u64::MAX)],
+            "miner",
+            &params,
+        );
+        db.accept_block(&out, &params).unwrap();
+        let back = mine_on(&out, vec![tx("bob", "alice", u64::MAX)], "miner", &params);
+        db.accept_block(&back, &params).unwrap();
+        let max = i128::from(u64::MAX);
+        assert_eq!(
+            db.balance_diff_between(0, 1).unwrap(),
+            [(addr("alice"), -max), (addr("bob"), max)]
+        );
+        assert_eq!(
+            db.balance_diff_between(0, 2).unwrap(),
+            [(addr("alice"), 0), (addr("bob"), 0)]
+        );
+
+        // a longer branch returns only 5 of it
+        let partial = mine_on(&out, vec![tx("bob", "alice", 5)], "other", &params);
+        db.accept_block(&partial, &params).unwrap();
+        let tip = mine_on(&partial, Vec::new(), "other", &params);
+        assert!(matches!(
+            db.accept_block(&tip, &params).unwrap(),
+            AcceptOutcome::Reorg { .. }
+        ));
+        assert_eq!(
+            db.balance_diff_between(0, 3).unwrap(),
+            [(addr("alice"), 5 - max), (addr("bob"), max - 5)]
+        );
+        assert_eq!(
+            db.balance_diff_between(1, 3).unwrap(),
+            [(addr("alice"), 5), (addr("bob"), -5)]
+        );
+        assert!(db.balance_diff_between(3, 4).is_err());
+    }
+
+    #[test]
+    fn height_of_follows_blocks_off_the_best_chain() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("height-of", &params);
+        let best = extend(&db, &genesis, 3, "miner", &params);
+        assert_eq!(db.height_of(&genesis.hash).unwrap(), Some(0));
+        for (i, block) in best.iter().enumerate() {
+            assert_eq!(db.height_of(&block.hash).unwrap(), Some(i as u64 + 1));
+        }
+
+        // a reorg leaves the old branch at its heights
+        let side = extend(&db, &best[0], 3, "side", &params);
+        assert_eq!(
+            db.hash_at_height(4).unwrap().as_deref(),
+            Some(&*side[2].hash)
+        );
+        assert_eq!(db.height_of(&best[1].hash).unwrap(), Some(2));
+        assert_eq!(db.height_of(&best[2].hash).unwrap(), Some(3));
+        assert_ne!(
+            db.hash_at_height(3).unwrap().as_deref(),
+            Some(&*best[2].hash)
+        );
+        assert_eq!(db.height_of(&side[2].hash).unwrap(), Some(4));
+
+        db.rollback_to(2).unwrap();
+        assert_eq!(db.height_of(&side[2].hash).unwrap(), None);
+        assert_eq!(db.height_of(&side[0].hash).unwrap(), Some(2));
+        assert_eq!(db.height_of("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn exported_block_imports_on_another_node() {
+        let params = ChainParams::testnet();
+        let (_dir_a, a, genesis) = chain("export-a", &params);
+        let (_dir_b, b, _) = chain("export-b", &params);
+        let block = mine_on(&genesis, vec![tx("alice", "bob", 5)], "miner", &params);
+        a.accept_block(&block, &params).unwrap();
+        let child = extend(&a, &block, 1, "miner", &params).remove(0);
+
+        let export = |hash: &str| serde_json::to_vec(&a.get_block(hash).unwrap().unwrap()).unwrap();
+        // b lacks the parent of `child`, so it cannot take it yet
+        let orphan = decode_block(&export(&child.hash)).unwrap();
+        assert!(b.accept_block(&orphan, &params).is_err());
+
+        let imported = decode_block(&export(&block.hash)).unwrap();
+        assert_eq!(imported.hash, block.hash);
+        assert!(failed_checks(&imported, &params).is_empty());
+        assert!(matches!(
+            b.accept_block(&imported, &params).unwrap(),
+            AcceptOutcome::Extended
+        ));
+        assert_eq!(b.hash_at_height(1).unwrap().as_deref(), Some(&*block.hash));
+        assert_eq!(b.reverify_block(&block.hash).unwrap(), Ok(()));
+        assert!(matches!(
+            b.accept_block(&orphan, &params).unwrap(),
+            AcceptOutcome::Extended
+        ));
+    }
+
+    #[test]
+    fn genesis_and_blocks_use_their_own_difficulty() {
+        let mut params = ChainParams::testnet();
+        let spec = GenesisSpec {
+            difficulty: 0,
+            block_difficulty: Some(8),
+            ..GenesisSpec::template(&params, params.genesis_timestamp)
+        };
+        spec.apply(&mut params).unwrap();
+        assert_eq!((params.genesis_difficulty, params.difficulty), (0, 8));
+
+        let (_dir, db, genesis) = chain("genesis-difficulty", &params);
+        assert_eq!(genesis.header.difficulty, 0);
+        // the genesis difficulty is part of its hash, so nodes must agree on it
+        assert_ne!(genesis.hash, make_genesis(&ChainParams::testnet()).hash);
+
+        assert_eq!(db.next_difficulty(&genesis.hash, &params).unwrap(), 8);
+        let first = mine_on(&genesis, Vec::new(), "miner", &params);
+        assert_eq!(first.header.difficulty, 8);
+        assert!(hash_to_difficulty(&first.hash) >= 8);
+        db.accept_block(&first, &params).unwrap();
+
+        let easy = mine_at(&genesis, Vec::new(), "easy", 0, &params);
+        assert!(db.accept_block(&easy, &params).is_err());
+    }
+
+    #[test]
+    fn epoch_retarget_survives_extreme_timestamps() {
+        let params = ChainParams::testnet();
+        let epoch = EpochRetarget { interval: 4 };
+        let slow = headers_at(&[i64::MIN, 0, 0, i64::MAX], 8);
+        assert_eq!(epoch.next_difficulty(&slow, &params), 6);
+        let backwards = headers_at(&[i64::MAX, 0, 0, i64::MIN], 8);
+        assert_eq!(epoch.next_difficulty(&backwards, &params), 10);
+
+        let huge_target = ChainParams {
+            target_block_time: i64::MAX,
+            ..ChainParams::testnet()
+        };
+        assert_eq!(epoch.next_difficulty(&slow, &huge_target), 8);
+    }
+
+    #[test]
+    fn lwma_survives_extreme_timestamps() {
+        let lwma = Lwma { window: 3 };
+        let params = ChainParams::testnet();
+        let zigzag = headers_at(&[i64::MIN, i64::MAX, i64::MIN, i64::MAX], 8);
+        assert_eq!(lwma.next_difficulty(&zigzag, &params), 7);
+        let backwards = headers_at(&[i64::MAX, 0, i64::MIN, i64::MIN], 8);
+        assert_eq!(lwma.next_difficulty(&backwards, &params), 9);
+
+        for target_block_time in [0, -5, i64::MAX] {
+            let params = ChainParams {
+                target_block_time,
+                ..ChainParams::testnet()
+            };
+            let d = lwma.next_difficulty(&zigzag, &params);
+            assert!((params.min_difficulty..=params.max_difficulty).contains(&d));
+        }
+    }
+
+    #[test]
+    fn retarget_after_a_minimum_timestamp_block() {
+        let params = ChainParams {
+            retarget: "lwma:4".parse().unwrap(),
+            ..ChainParams::regtest()
+        };
+        let (_dir, db, genesis) = chain("min-timestamp", &params);
+        let mut block = build_block(&genesis, Vec::new(), addr("miner"), 0, i64::MIN, 0, &params)
+            .unwrap()
+            .mine(NonceStride::default());
+        db.accept_block(&block, &params).unwrap();
+        for timestamp in [i64::MAX, i64::MIN] {
+            let difficulty = db.next_difficulty(&block.hash, &params).unwrap();
+            block = build_block(
+                &block,
+                Vec::new(),
+                addr("miner"),
+                0,
+                timestamp,
+                difficulty,
+                &params,
+            )
+            .unwrap()
+            .mine(NonceStride::default());
+            db.accept_block(&block, &params).unwrap();
+        }
+        assert_eq!(db.tip_height().unwrap(), Some(3));
+    }
+
+    #[test]
+    fn file_store_next_difficulty_reads_only_the_window() {
+        let dir = TempDir::new("file-window");
+        let store = FileStore::open(dir.path()).unwrap();
+        let mut params = ChainParams::regtest();
+        let genesis = make_genesis(&params);
+        store.accept_block(&genesis, &params).unwrap();
+        let blocks = extend(&store, &genesis, 3, "miner", &params);
+        let tip = &blocks[2].hash;
+        fs::remove_file(dir.path().join("blocks").join("0.bin")).unwrap();
+
+        // lwma:2 needs heights 1..=3 and fixed needs no headers at all
+        params.retarget = "lwma:2".parse().unwrap();
+        assert!(store.next_difficulty(tip, &params).is_ok());
+        fs::remove_file(dir.path().join("blocks").join("1.bin")).unwrap();
+        let err = store.next_difficulty(tip, &params).unwrap_err();
+        assert!(err.to_string().contains("height 1"), "{:#}", err);
+        params.retarget = Retarget::Fixed;
+        assert_eq!(store.next_difficulty(tip, &params).unwrap(), 0);
+    }
+
+    #[test]
+    fn header_export_round_trips() {
+        let params = ChainParams {
+            retarget: "lwma:2".parse().unwrap(),
+            ..ChainParams::regtest()
+        };
+        let (_dir, db, genesis) = chain("headers", &params);
+        let blocks = extend(&db, &genesis, 5, "miner", &params);
+        let mut bytes = Vec::new();
+        assert_eq!(db.export_headers(&mut bytes).unwrap(), 6);
+
+        let headers = import_headers(&bytes[..], &params).unwrap();
+        assert_eq!(headers.len(), 6);
+        assert_eq!(headers.hash_at(0), Some(genesis.hash.as_str()));
+        assert_eq!(headers.tip_hash(), Some(blocks[4].hash.as_str()));
+        assert_eq!(
+            headers.header_at(3).unwrap().difficulty,
+            blocks[2].header.difficulty
+        );
+
+        // another network's rules reject the same headers
+        let fixed = ChainParams {
+            difficulty: 4,
+            ..ChainParams::regtest()
+        };
+        let err = import_headers(&bytes[..], &fixed).unwrap_err();
+        assert!(format!("{:#}", err).contains("requires 4"), "{:#}", err);
+        let err = import_headers(&bytes[..], &ChainParams::testnet()).unwrap_err();
+        assert!(err.to_string().contains("genesis"), "{:#}", err);
+        assert!(import_headers(&bytes[1..], &params).is_err());
+    }
+
+    #[test]
+    fn header_import_rejects_unworked_headers() {
+        let params = ChainParams::testnet();
+        let (_dir, db, genesis) = chain("unworked", &params);
+        let mut header = BlockHeader {
+            version: 0,
+            parent_hash: genesis.hash.clone(),
+            merkle_root: String::new(),
+            timestamp: genesis.header.timestamp + 10,
+            nonce: 0,
+            difficulty: params.difficulty,
+            miner: addr("miner"),
+        };
+        while meets_difficulty(&hash_block_header(&header), header.difficulty) {
+            header.nonce += 1;
+        }
+        // stored without validation, as a peer could send it
+        let hash = hash_block_header(&header);
+        db.save_block(&Block {
+            header,
+            txs: Vec::new(),
+            hash,
+        })
+        .unwrap();
+        let mut bytes = Vec::new();
+        db.export_headers(&mut bytes).unwrap();
+        let err = import_headers(&bytes[..], &params).unwrap_err();
+        assert!(format!("{:#}", err).contains("height 1"), "{:#}", err);
+    }
+
+    #[test]
+    fn reorg_reinsertion_is_not_charged_to_velocity() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("reorg-velocity", &params);
+        let mut pool = Mempool::new(None).with_velocity_limit("100/10".parse().unwrap());
+        let spend = tx("alice", "bob", 60);
+        pool.add(spend.clone(), 0, &params).unwrap();
+        let mined = mine_on(&genesis, pool.txs(), "miner", &params);
+        db.accept_block(&mined, &params).unwrap();
+        pool.remove_included(&mined);
+
+        let fork = mine_on(&genesis, Vec::new(), "other", &params);
+        db.accept_block(&fork, &params).unwrap();
+        let fork_tip = mine_on(&fork, Vec::new(), "other", &params);
+        let outcome = db.accept_block(&fork_tip, &params).unwrap();
+        assert!(matches!(outcome, AcceptOutcome::Reorg { .. }));
+
+        assert_eq!(pool.reconstruct_after_reorg(&outcome, 2, &params), 1);
+        assert_eq!(pool.txs(), [spend]);
+        // the 60 still counts once, leaving room for exactly 40 more
+        pool.add(tx("alice", "bob", 40), 2, &params).unwrap();
+        assert!(pool.add(tx("alice", "bob", 1), 2, &params).is_err());
+    }
+
+    #[test]
+    fn startup_report_checks_the_derived_indexes() {
+        let params = ChainParams::regtest();
+        let (_dir, db, genesis) = chain("startup", &params);
+        extend(&db, &genesis, 3, "miner", &params);
+        let report = db.startup_report().unwrap();
+        assert!(report.is_ready(), "{}", report);
+        assert_eq!(report.verified_height, Some(3));
+
+        // the chain itself still verifies; only the height index is wrong
+        db.db.remove(height_key(2)).unwrap();
+        let report = db.startup_report().unwrap();
+        assert!(!report.indexes_consistent);
+        assert!(!report.is_ready());
+        assert!(
+            report.errors.iter().any(|e| e.starts_with("height index")),
+            "{}",
+            report
+        );
+    }
+}
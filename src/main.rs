@@ -1,231 +1,745 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, ensure, Result};
 use chrono::Utc;
+use pow_mvp_rust::{
+    block_work, build_block, decode_block_with, decode_tx, hash_block_header, hash_to_difficulty,
+    is_disk_full, jittered, make_genesis, merkle_root, open_store, standalone_checks,
+    standalone_tx_checks, AcceptOutcome, Address, Block, ChainDB, ChainParams, Decoding,
+    GenesisSpec, Mempool, NodeConfig, NonceStride, Store, StoreKind, Transaction,
+};
 use rand::Rng;
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use sled::Db;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::thread;
 use std::time::Duration;
-use std::{thread, vec};
-
-// -----------------------------
-// Types
-// -----------------------------
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Transaction {
-    pub from: String,
-    pub to: String,
-    pub amount: u64,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct BlockHeader {
-    pub parent_hash: String,
-    pub merkle_root: String,
-    pub timestamp: i64,
-    pub nonce: u64,
-    pub difficulty: u32,
-    pub miner: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Block {
-    pub header: BlockHeader,
-    pub txs: Vec<Transaction>,
-    pub hash: String,
-}
-
-// -----------------------------
-// Helpers
-// -----------------------------
-
-fn hash_block_header(h: &BlockHeader) -> String {
-    let serialized = serde_json::to_string(h).expect("serialize header");
-    let mut hasher = Sha256::new();
-    hasher.update(serialized.as_bytes());
-    hex::encode(hasher.finalize())
-}
-
-fn hash_block(block: &Block) -> String {
-    let serialized = serde_json::to_string(block).expect("serialize block");
-    let mut hasher = Sha256::new();
-    hasher.update(serialized.as_bytes());
-    hex::encode(hasher.finalize())
-}
-
-fn merkle_root(txs: &Vec<Transaction>) -> String {
-    // very simple merkle-like: hash concatenation pairs until single hash
-    if txs.is_empty() {
-        return "".to_string();
-    }
-    let mut leaves: Vec<String> = txs
-        .iter()
-        .map(|t| {
-            let s = serde_json::to_string(t).unwrap();
-            let mut h = Sha256::new();
-            h.update(s.as_bytes());
-            hex::encode(h.finalize())
-        })
-        .collect();
 
-    while leaves.len() > 1 {
-        let mut next = Vec::new();
-        for pair in leaves.chunks(2) {
-            if pair.len() == 1 {
-                next.push(pair[0].clone());
-            } else {
-                let mut h = Sha256::new();
-                h.update(pair[0].as_bytes());
-                h.update(pair[1].as_bytes());
-                next.push(hex::encode(h.finalize()));
+/// Pause between mined blocks, before jitter.
+const MINING_PAUSE: Duration = Duration::from_secs(1);
+
+/// How long to wait before retrying a save that hit a full disk.
+const DISK_FULL_RETRY: Duration = Duration::from_secs(10);
+
+enum Mode {
+    Node,
+    ValidateOnly(String),
+    CheckTx(String),
+    DumpBlock(String),
+    ExportStats(String),
+    Reindex,
+    ReverifyBlock(String),
+    ExportBlock(String),
+    ImportBlock(String),
+    /// Print a genesis spec for the selected network, optionally at a
+    /// fixed timestamp instead of now.
+    GenesisTemplate(Option<i64>),
+}
+
+struct Args {
+    config: NodeConfig,
+    mode: Mode,
+}
+
+/// Parses the command line after the program name.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args> {
+    let mut config = NodeConfig::default();
+    let mut mode = Mode::Node;
+    let (mut miner_id, mut miner_count) = (0, 1);
+    let mut genesis_timestamp = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "--mempool-tx-max-blocks" => config.mempool_tx_max_blocks = Some(value()?.parse()?),
+            "--max-mempool-ancestors" => config.max_mempool_ancestors = Some(value()?.parse()?),
+            "--spend-velocity" => config.spend_velocity = Some(value()?.parse()?),
+            "--validate-only" => mode = Mode::ValidateOnly(value()?),
+            "--check-tx" => mode = Mode::CheckTx(value()?),
+            "--dump-block" => mode = Mode::DumpBlock(value()?),
+            "--export-stats" => mode = Mode::ExportStats(value()?),
+            "--reindex" => mode = Mode::Reindex,
+            "--reverify-block" => mode = Mode::ReverifyBlock(value()?),
+            "--export-block" => mode = Mode::ExportBlock(value()?),
+            "--import-block" => mode = Mode::ImportBlock(value()?),
+            "--genesis-template" => mode = Mode::GenesisTemplate(None),
+            "--genesis-timestamp" => genesis_timestamp = Some(value()?.parse()?),
+            "--genesis-spec" => config.genesis_spec = Some(value()?.into()),
+            "--retarget" => config.retarget = Some(value()?.parse()?),
+            "--network" => config.network = value()?.parse()?,
+            "--decoding" => config.decoding = value()?.parse()?,
+            "--store" => config.store = value()?.parse()?,
+            "--audit-log" => config.audit_log = Some(value()?.into()),
+            "--version-bits" => config.version_bits = value()?.parse()?,
+            "--auto-checkpoint-interval" => {
+                config.auto_checkpoint_interval = Some(value()?.parse()?)
             }
+            "--mining-jitter-ms" => config.mining_jitter = Duration::from_millis(value()?.parse()?),
+            "--miner-id" => miner_id = value()?.parse()?,
+            "--miner-count" => miner_count = value()?.parse()?,
+            "--resync-from" => config.resync_from = Some(value()?.parse()?),
+            "--mine-address" => config.mine_address = Some(value()?.parse()?),
+            other => bail!("unknown argument: {}", other),
         }
-        leaves = next;
     }
-    leaves[0].clone()
+    config.nonce_stride = NonceStride::new(miner_id, miner_count)?;
+    match &mut mode {
+        Mode::GenesisTemplate(timestamp) => *timestamp = genesis_timestamp,
+        _ => ensure!(
+            genesis_timestamp.is_none(),
+            "--genesis-timestamp only applies to --genesis-template"
+        ),
+    }
+    Ok(Args { config, mode })
 }
 
-// -----------------------------
-// Storage wrapper (sled)
-// -----------------------------
-
-struct ChainDB {
-    db: Db,
+/// Address credited for blocks this node mines: `--mine-address`, or a
+/// fixed demo address.
+fn miner_address(config: &NodeConfig) -> Address {
+    config
+        .mine_address
+        .clone()
+        .unwrap_or_else(|| Address::parse("miner_1").expect("valid address"))
 }
 
-impl ChainDB {
-    fn open(path: &str) -> Result<Self> {
-        let db = sled::open(path)?;
-        Ok(Self { db })
+/// Accepts `block`, waiting `retry` between attempts for as long as the
+/// disk is full. Any other error is returned.
+fn accept_with_retry(
+    store: &dyn Store,
+    block: &Block,
+    params: &ChainParams,
+    retry: Duration,
+) -> Result<AcceptOutcome> {
+    loop {
+        match store.accept_block(block, params) {
+            Ok(outcome) => return Ok(outcome),
+            // keep the block and wait for space rather than exiting
+            Err(e) if is_disk_full(&e) => {
+                println!(
+                    "[ERROR] disk full saving block {}; mining paused, retrying in {}s",
+                    &block.hash[..12],
+                    retry.as_secs()
+                );
+                thread::sleep(retry);
+            }
+            Err(e) => return Err(e),
+        }
     }
+}
 
-    fn save_block(&self, block: &Block) -> Result<()> {
-        let key = block.hash.clone();
-        let val = serde_json::to_vec(block)?;
-        self.db.insert(key.as_bytes(), val)?;
-        // store latest height reference
-        self.db.insert(b"latest", block.hash.as_bytes())?;
-        self.db.flush()?;
-        Ok(())
+/// Runs the standalone checks on a serialized block without opening the DB.
+fn validate_only(path: &str, params: &ChainParams, decoding: Decoding) -> Result<()> {
+    let block = decode_block_with(&std::fs::read(path)?, decoding)?;
+    println!("Validating block {} from {}", block.hash, path);
+    let checks = standalone_checks(&block, params);
+    let mut failed = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(()) => println!("  [PASS] {}", name),
+            Err(e) => {
+                failed += 1;
+                println!("  [FAIL] {}: {}", name, e);
+            }
+        }
     }
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, checks.len());
+    }
+    println!("Block is valid");
+    Ok(())
+}
 
-    fn get_latest(&self) -> Result<Option<Block>> {
-        if let Some(v) = self.db.get(b"latest")? {
-            let hash = String::from_utf8(v.to_vec())?;
-            if let Some(bv) = self.db.get(hash.as_bytes())? {
-                let block: Block = serde_json::from_slice(&bv)?;
-                return Ok(Some(block));
+/// Runs the standalone checks on a JSON transaction without opening the DB.
+fn check_tx(path: &str, params: &ChainParams, decoding: Decoding) -> Result<()> {
+    let tx = decode_tx(&std::fs::read(path)?, decoding)
+        .map_err(|e| anyhow!("malformed transaction in {}: {}", path, e))?;
+    println!("Checking transaction {} from {}", tx.txid(), path);
+    let checks = standalone_tx_checks(&tx, params);
+    let mut failed = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(()) => println!("  [PASS] {}", name),
+            Err(e) => {
+                failed += 1;
+                println!("  [FAIL] {}: {}", name, e);
             }
         }
-        Ok(None)
     }
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, checks.len());
+    }
+    println!("Transaction is well-formed");
+    Ok(())
+}
+
+/// Prints a stored block, looked up by hash or best-chain height, with
+/// its recomputed hash and the result of every standalone check.
+fn dump_block(config: &NodeConfig, target: &str, params: &ChainParams) -> Result<()> {
+    let store = open_store(config)?;
+    let dump = block_dump(&*store, target, params)?;
+    println!("{}", serde_json::to_string_pretty(&dump)?);
+    Ok(())
 }
 
-// -----------------------------
-// Mining / PoW
-// -----------------------------
+/// The `--dump-block` report for `target` in `store`.
+fn block_dump(store: &dyn Store, target: &str, params: &ChainParams) -> Result<serde_json::Value> {
+    let block = match target.parse::<u64>() {
+        Ok(height) if target.len() < 64 => store.block_at_height(height)?,
+        _ => store.get_block(target)?,
+    }
+    .ok_or_else(|| anyhow!("no block {} in the store", target))?;
 
-fn meets_difficulty(hex_hash: &str, difficulty: u32) -> bool {
-    // simple target: count leading zero nibbles (hex characters)
-    let needed = (difficulty as usize) / 4; // approximate
-    hex_hash.starts_with(&"0".repeat(needed))
+    let checks: Vec<_> = standalone_checks(&block, params)
+        .into_iter()
+        .map(|(name, result)| {
+            json!({
+                "check": name,
+                "result": match result {
+                    Ok(()) => "pass".to_string(),
+                    Err(e) => format!("fail: {}", e),
+                },
+            })
+        })
+        .collect();
+    let h = &block.header;
+    Ok(json!({
+        "hash": block.hash,
+        "computed_hash": hash_block_header(h),
+        // luck: what the hash achieved against what the header required
+        "achieved_difficulty": hash_to_difficulty(&block.hash),
+        "header": {
+            "version": format!("{:#010x}", h.version),
+            "parent_hash": h.parent_hash,
+            "merkle_root": h.merkle_root,
+            "computed_merkle_root": merkle_root(&block.txs),
+            "timestamp": h.timestamp,
+            "nonce": format!("{:#018x}", h.nonce),
+            "difficulty": h.difficulty,
+            "miner": h.miner,
+        },
+        "txs": block.txs,
+        "checks": checks,
+    }))
 }
 
-fn mine_block(header_template: &BlockHeader, txs: &Vec<Transaction>) -> Block {
-    let mut header = header_template.clone();
-    loop {
-        header.nonce = rand::thread_rng().gen();
-        let mut header_for_hash = header.clone();
-        // recompute merkle root in case txs changed
-        header_for_hash.merkle_root = merkle_root(txs);
-        let h = hash_block_header(&header_for_hash);
-        if meets_difficulty(&h, header_for_hash.difficulty) {
-            let block = Block {
-                header: header_for_hash,
-                txs: txs.clone(),
-                hash: h.clone(),
-            };
-            return block;
-        }
+/// Writes one CSV row per best-chain block, genesis first. Blocks are read
+/// one height at a time, so the chain is never held in memory.
+fn export_stats(config: &NodeConfig, path: &str) -> Result<()> {
+    let store = open_store(config)?;
+    let height = write_stats(&*store, BufWriter::new(File::create(path)?))?;
+    println!("Exported {} blocks to {}", height, path);
+    Ok(())
+}
+
+/// Writes the `--export-stats` CSV for `store`, returning the row count.
+fn write_stats(store: &dyn Store, mut out: impl Write) -> Result<u64> {
+    writeln!(
+        out,
+        "height,hash,timestamp,interval,tx_count,difficulty,achieved_difficulty,cumulative_work"
+    )?;
+    let mut work: u128 = 0;
+    let mut parent_timestamp: Option<i64> = None;
+    let mut height = 0;
+    while let Some(block) = store.block_at_height(height)? {
+        let h = &block.header;
+        work += block_work(h.difficulty);
+        // genesis has no parent, so its interval is left empty
+        let interval = parent_timestamp
+            .map(|t| (h.timestamp - t).to_string())
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            height,
+            block.hash,
+            h.timestamp,
+            interval,
+            block.txs.len(),
+            h.difficulty,
+            hash_to_difficulty(&block.hash),
+            work
+        )?;
+        parent_timestamp = Some(h.timestamp);
+        height += 1;
+    }
+    out.flush()?;
+    Ok(height)
+}
+
+/// Reports index drift against the stored blocks, then rebuilds the
+/// indexes if any was found.
+fn reindex(config: &NodeConfig) -> Result<()> {
+    ensure!(
+        config.store == StoreKind::Sled,
+        "--reindex only applies to the sled store"
+    );
+    let db = ChainDB::open(config.store_path())?;
+    let report = db.verify_indexes()?;
+    println!("[INFO] index check: {}", report);
+    if report.is_consistent() {
+        return Ok(());
     }
+    let reindexed = db.reindex()?;
+    println!("Reindexed {} blocks", reindexed);
+    println!("[INFO] index check: {}", db.verify_indexes()?);
+    Ok(())
 }
 
-// -----------------------------
-// Application (MVP node)
-// -----------------------------
+/// Re-checks one stored block suspected of corruption.
+fn reverify_block(config: &NodeConfig, hash: &str) -> Result<()> {
+    ensure!(
+        config.store == StoreKind::Sled,
+        "--reverify-block only applies to the sled store"
+    );
+    let db = ChainDB::open(config.store_path())?;
+    db.reverify_block(hash)?
+        .map_err(|e| anyhow!("block {} failed re-verification: {}", hash, e))?;
+    println!("Block {} re-verified", hash);
+    Ok(())
+}
+
+/// Writes a stored block to stdout in the serialized form
+/// `--import-block` and `--validate-only` read.
+fn export_block(config: &NodeConfig, hash: &str) -> Result<()> {
+    let store = open_store(config)?;
+    let block = store
+        .get_block(hash)?
+        .ok_or_else(|| anyhow!("no block {} in the store", hash))?;
+    println!("{}", serde_json::to_string(&block)?);
+    Ok(())
+}
 
-fn make_genesis(difficulty: u32) -> Block {
-    let header = BlockHeader {
-        parent_hash: String::from("0"),
-        merkle_root: String::from(""),
-        timestamp: Utc::now().timestamp(),
-        nonce: 0,
-        difficulty,
-        miner: String::from("genesis"),
+/// Validates and stores one hand-delivered block whose parent is already
+/// stored.
+fn import_block(config: &NodeConfig, path: &str, params: &ChainParams) -> Result<()> {
+    let block = decode_block_with(&std::fs::read(path)?, config.decoding)?;
+    let store = open_store(config)?;
+    ensure!(
+        store.get_block(&block.header.parent_hash)?.is_some(),
+        "parent {} of block {} is not in the store",
+        block.header.parent_hash,
+        block.hash
+    );
+    let outcome = match store.accept_block(&block, params)? {
+        AcceptOutcome::AlreadyKnown => "was already stored",
+        AcceptOutcome::Extended => "extended the tip",
+        AcceptOutcome::SideBranch => "was stored on a side branch",
+        AcceptOutcome::Reorg { .. } => "reorganized the chain",
     };
-    let txs: Vec<Transaction> = vec![];
-    let mut header_for_hash = header.clone();
-    header_for_hash.merkle_root = merkle_root(&txs);
-    let h = hash_block_header(&header_for_hash);
-    Block {
-        header: header_for_hash,
-        txs,
-        hash: h,
-    }
+    println!("Imported block {}: it {}", block.hash, outcome);
+    Ok(())
 }
 
 fn main() -> Result<()> {
+    let Args { config, mode } = parse_args(std::env::args().skip(1))?;
+    let mut params = ChainParams::for_network(config.network);
+    if let Some(path) = &config.genesis_spec {
+        GenesisSpec::from_json(&std::fs::read(path)?)?.apply(&mut params)?;
+    }
+    if let Some(retarget) = config.retarget {
+        params.retarget = retarget;
+    }
+    match mode {
+        Mode::ValidateOnly(path) => return validate_only(&path, &params, config.decoding),
+        Mode::CheckTx(path) => return check_tx(&path, &params, config.decoding),
+        Mode::DumpBlock(target) => return dump_block(&config, &target, &params),
+        Mode::ExportStats(path) => return export_stats(&config, &path),
+        Mode::Reindex => return reindex(&config),
+        Mode::ReverifyBlock(hash) => return reverify_block(&config, &hash),
+        Mode::ExportBlock(hash) => return export_block(&config, &hash),
+        Mode::ImportBlock(path) => return import_block(&config, &path, &params),
+        Mode::GenesisTemplate(timestamp) => {
+            let timestamp = timestamp.unwrap_or_else(|| Utc::now().timestamp());
+            let spec = GenesisSpec::template(&params, timestamp);
+            println!("{}", serde_json::to_string_pretty(&spec)?);
+            return Ok(());
+        }
+        Mode::Node => {}
+    }
+
     println!("PoW MVP node (single-process).\nStarting...");
 
     // Open DB
-    let chain_db = ChainDB::open("./chain_db")?;
-
-    // Difficulty: number of leading zero bits approximation (use small for testing)
-    let difficulty: u32 = 12; // tune this: higher -> slower
+    let chain_db = open_store(&config)?;
 
     // If no chain, write genesis
     if chain_db.get_latest()?.is_none() {
-        let genesis = make_genesis(difficulty);
-        chain_db.save_block(&genesis)?;
+        let genesis = make_genesis(&params);
+        chain_db.accept_block(&genesis, &params)?;
         println!("Saved genesis: {}", genesis.hash);
     }
 
+    // Drop everything above a known-good height and rebuild from there
+    if let Some(resync_height) = config.resync_from {
+        let removed = chain_db.rollback_to(resync_height)?;
+        println!(
+            "Rolled back {} blocks; resyncing from height {}",
+            removed, resync_height
+        );
+    }
+
+    // Self-check before mining on top of whatever is on disk
+    let report = chain_db.startup_report()?;
+    println!("[INFO] startup check: {}", report);
+    if !report.is_ready() {
+        bail!("node failed its startup self-check");
+    }
+    let mut height = report.verified_height.expect("ready implies verified");
+
+    let miner = miner_address(&config);
+
     // Simple mempool of random txs for demo
-    let mut mempool: Vec<Transaction> = Vec::new();
+    let mut mempool = Mempool::new(config.mempool_tx_max_blocks);
+    if let Some(max) = config.max_mempool_ancestors {
+        mempool = mempool.with_max_ancestors(max);
+    }
+    if let Some(limit) = config.spend_velocity {
+        mempool = mempool.with_velocity_limit(limit);
+    }
 
     // Spawn a miner loop (single-threaded for MVP)
     loop {
         // populate mempool with a few random txs
         for _ in 0..3 {
             let t = Transaction {
-                from: format!("addr_{}", rand::thread_rng().gen::<u16>()),
-                to: format!("addr_{}", rand::thread_rng().gen::<u16>()),
+                from: Address::parse(&format!("addr_{}", rand::thread_rng().gen::<u16>()))?,
+                to: Address::parse(&format!("addr_{}", rand::thread_rng().gen::<u16>()))?,
                 amount: rand::thread_rng().gen_range(1..1000),
             };
-            mempool.push(t);
+            if let Err(e) = mempool.add(t, height, &params) {
+                println!("Rejected tx: {}", e);
+            }
         }
 
         // get parent
         let parent = chain_db.get_latest()?.expect("latest exists");
-        let header = BlockHeader {
-            parent_hash: parent.hash.clone(),
-            merkle_root: String::new(),
-            timestamp: Utc::now().timestamp(),
-            nonce: 0,
+        let difficulty = chain_db.next_difficulty(&parent.hash, &params)?;
+        let template = build_block(
+            &parent,
+            mempool.txs(),
+            miner.clone(),
+            config.version_bits,
+            Utc::now().timestamp(),
             difficulty,
-            miner: String::from("miner_1"),
-        };
+            &params,
+        )?;
 
         println!("Mining new block on parent {}...", &parent.hash[..8]);
-        let block = mine_block(&header, &mempool);
+        let block = template.mine(config.nonce_stride);
         let block_hash = block.hash.clone();
-        chain_db.save_block(&block)?;
+        let outcome = accept_with_retry(&*chain_db, &block, &params, DISK_FULL_RETRY)?;
+        height += 1;
         println!("Mined block {} with {} txs (nonce={})", &block_hash[..12], block.txs.len(), block.header.nonce);
 
-        // clear mempool
-        mempool.clear();
+        // drop what was mined, then anything that has waited too long
+        mempool.remove_included(&block);
+        let reinserted = mempool.reconstruct_after_reorg(&outcome, height, &params);
+        if reinserted > 0 {
+            println!("Returned {} reorged txs to the mempool", reinserted);
+        }
+        let evicted = mempool.evict_expired(height);
+        if evicted > 0 {
+            println!("Evicted {} stale mempool txs", evicted);
+        }
 
         // wait a bit to avoid busy loop in demo (adjust as needed)
-        thread::sleep(Duration::from_secs(1));
+        thread::sleep(jittered(MINING_PAUSE, config.mining_jitter));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pow_mvp_rust::FileStore;
+    use std::cell::Cell;
+    use std::io;
+    use std::path::PathBuf;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    /// Writes `bytes` to a scratch file named for the test.
+    fn scratch_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pow-mvp-main-{}-{}", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn mined_block(params: &ChainParams) -> Block {
+        mined_block_by(params, "miner".parse().unwrap())
+    }
+
+    fn mined_block_by(params: &ChainParams, miner: Address) -> Block {
+        let genesis = make_genesis(params);
+        let tx = Transaction {
+            from: "alice".parse().unwrap(),
+            to: "bob".parse().unwrap(),
+            amount: 3,
+        };
+        build_block(
+            &genesis,
+            vec![tx],
+            miner,
+            0,
+            genesis.header.timestamp + 10,
+            params.difficulty,
+            params,
+        )
+        .unwrap()
+        .mine(NonceStride::default())
+    }
+
+    #[test]
+    fn validate_only_rejects_a_tampered_block() {
+        let params = ChainParams {
+            difficulty: 8,
+            ..ChainParams::default()
+        };
+        let mut block = mined_block(&params);
+        let good = scratch_file("validate-good", &serde_json::to_vec(&block).unwrap());
+        block.txs[0].amount = 300;
+        let bad = scratch_file("validate-bad", &serde_json::to_vec(&block).unwrap());
+
+        let good_result = validate_only(good.to_str().unwrap(), &params, Decoding::Strict);
+        let bad_result = validate_only(bad.to_str().unwrap(), &params, Decoding::Strict);
+        let _ = (std::fs::remove_file(good), std::fs::remove_file(bad));
+        good_result.unwrap();
+        let err = bad_result.unwrap_err();
+        assert!(
+            err.to_string().contains("1 of 6 checks failed"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn genesis_timestamp_modifies_the_template() {
+        for args in [
+            ["--genesis-timestamp", "5", "--genesis-template"],
+            ["--genesis-template", "--genesis-timestamp", "5"],
+        ] {
+            let mode = parse(&args).unwrap().mode;
+            assert!(matches!(mode, Mode::GenesisTemplate(Some(5))), "{:?}", args);
+        }
+        let mode = parse(&["--genesis-template"]).unwrap().mode;
+        assert!(matches!(mode, Mode::GenesisTemplate(None)));
+        assert!(parse(&["--genesis-timestamp", "5"]).is_err());
+    }
+
+    #[test]
+    fn mined_blocks_credit_the_mine_address() {
+        let params = ChainParams {
+            difficulty: 4,
+            ..ChainParams::default()
+        };
+        let config = parse(&["--mine-address", "pool_7"]).unwrap().config;
+        let block = mined_block_by(&params, miner_address(&config));
+        assert_eq!(block.header.miner.as_str(), "pool_7");
+        assert!(standalone_checks(&block, &params)
+            .iter()
+            .all(|c| c.1.is_ok()));
+
+        let default = parse(&[]).unwrap().config;
+        assert_eq!(miner_address(&default).as_str(), "miner_1");
+        assert!(parse(&["--mine-address", "not valid"]).is_err());
+    }
+
+    /// Scratch directory named for the test, emptied first.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pow-mvp-main-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn dump_block_reports_the_stored_block() {
+        let params = ChainParams::testnet();
+        let dir = scratch_dir("dump-block");
+        let db = ChainDB::open(&dir).unwrap();
+        db.accept_block(&make_genesis(&params), &params).unwrap();
+        let block = mined_block(&params);
+        db.accept_block(&block, &params).unwrap();
+
+        let by_height = block_dump(&db, "1", &params).unwrap();
+        let by_hash = block_dump(&db, &block.hash, &params).unwrap();
+        assert!(block_dump(&db, "2", &params).is_err());
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(by_height, by_hash);
+        assert_eq!(by_hash["hash"], block.hash.as_str());
+        assert_eq!(by_hash["computed_hash"], block.hash.as_str());
+        assert_eq!(by_hash["header"]["difficulty"], params.difficulty);
+        let checks = by_hash["checks"].as_array().unwrap();
+        assert_eq!(checks.len(), 6);
+        assert!(checks.iter().all(|c| c["result"] == "pass"), "{:?}", checks);
+        let achieved = by_hash["achieved_difficulty"].as_u64().unwrap();
+        assert!(achieved >= u64::from(params.difficulty));
+    }
+
+    /// File store whose next `failures` writes fail with `kind`.
+    struct FaultyStore {
+        inner: FileStore,
+        failures: Cell<u32>,
+        kind: io::ErrorKind,
+    }
+
+    impl Store for FaultyStore {
+        fn save_block(&self, block: &Block) -> Result<()> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err(io::Error::from(self.kind).into());
+            }
+            self.inner.save_block(block)
+        }
+
+        fn get_block(&self, hash: &str) -> Result<Option<Block>> {
+            self.inner.get_block(hash)
+        }
+
+        fn block_at_height(&self, height: u64) -> Result<Option<Block>> {
+            self.inner.block_at_height(height)
+        }
+
+        fn latest_hash(&self) -> Result<Option<String>> {
+            self.inner.latest_hash()
+        }
+
+        fn schema_version(&self) -> Result<Option<u32>> {
+            self.inner.schema_version()
+        }
+
+        fn expected_schema_version(&self) -> u32 {
+            self.inner.expected_schema_version()
+        }
+
+        fn rollback_to(&self, height: u64) -> Result<u64> {
+            self.inner.rollback_to(height)
+        }
+    }
+
+    #[test]
+    fn disk_full_saves_are_retried() {
+        let params = ChainParams::regtest();
+        let dir = scratch_dir("disk-full");
+        let store = FaultyStore {
+            inner: FileStore::open(&dir).unwrap(),
+            failures: Cell::new(0),
+            kind: io::ErrorKind::StorageFull,
+        };
+        let genesis = make_genesis(&params);
+        store.accept_block(&genesis, &params).unwrap();
+
+        let block = mined_block(&params);
+        store.failures.set(2);
+        let outcome = accept_with_retry(&store, &block, &params, Duration::ZERO).unwrap();
+        assert!(matches!(outcome, AcceptOutcome::Extended));
+        assert_eq!(store.failures.get(), 0);
+        assert_eq!(store.latest_hash().unwrap(), Some(block.hash.clone()));
+
+        // anything else is not waited out
+        let store = FaultyStore {
+            kind: io::ErrorKind::PermissionDenied,
+            ..store
+        };
+        store.failures.set(1);
+        let next = build_block(
+            &block,
+            Vec::new(),
+            "miner".parse().unwrap(),
+            0,
+            block.header.timestamp + 10,
+            0,
+            &params,
+        )
+        .unwrap()
+        .mine(NonceStride::default());
+        assert!(accept_with_retry(&store, &next, &params, Duration::ZERO).is_err());
+        drop(store);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn export_stats_writes_one_row_per_block() {
+        let params = ChainParams::testnet();
+        let dir = scratch_dir("export-stats");
+        let db = ChainDB::open(&dir).unwrap();
+        let genesis = make_genesis(&params);
+        db.accept_block(&genesis, &params).unwrap();
+        let block = mined_block(&params);
+        db.accept_block(&block, &params).unwrap();
+        let mut csv = Vec::new();
+        let rows = write_stats(&db, &mut csv).unwrap();
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(rows, 2);
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            [
+                "height",
+                "hash",
+                "timestamp",
+                "interval",
+                "tx_count",
+                "difficulty",
+                "achieved_difficulty",
+                "cumulative_work"
+            ]
+        );
+        assert_eq!(lines[1][..5], ["0", &genesis.hash, "1704153600", "", "0"]);
+        assert_eq!(lines[2][..5], ["1", &block.hash, "1704153610", "10", "1"]);
+        assert_eq!(lines[2][5], "8");
+        let achieved: u32 = lines[2][6].parse().unwrap();
+        assert!(achieved >= 8);
+        let work: u128 = lines[2][7].parse().unwrap();
+        assert_eq!(work, block_work(8) * 2);
+    }
+
+    #[test]
+    fn check_tx_accepts_good_and_rejects_bad_transactions() {
+        let params = ChainParams::regtest();
+        let cases: [(&str, &[u8], Decoding, bool); 5] = [
+            (
+                "good",
+                br#"{"from":"alice","to":"bob","amount":5}"#,
+                Decoding::Strict,
+                true,
+            ),
+            (
+                "dust",
+                br#"{"from":"alice","to":"bob","amount":0}"#,
+                Decoding::Strict,
+                false,
+            ),
+            (
+                "address",
+                br#"{"from":"al ice","to":"bob","amount":5}"#,
+                Decoding::Strict,
+                false,
+            ),
+            (
+                "extra",
+                br#"{"from":"alice","to":"bob","amount":5,"fee":1}"#,
+                Decoding::Strict,
+                false,
+            ),
+            (
+                "lenient",
+                br#"{"from":"alice","to":"bob","amount":5,"fee":1}"#,
+                Decoding::Lenient,
+                true,
+            ),
+        ];
+        for (name, json, decoding, ok) in cases {
+            let path = scratch_file(&format!("check-tx-{}", name), json);
+            let result = check_tx(path.to_str().unwrap(), &params, decoding);
+            let _ = std::fs::remove_file(path);
+            assert_eq!(result.is_ok(), ok, "{}: {:?}", name, result.err());
+        }
     }
 }
@@ -0,0 +1,261 @@
+//! Framing for messages exchanged between nodes.
+//!
+//! Storage keeps blocks as JSON. The wire format is binary and versioned
+//! on its own, so either can change without the other. Every message is a
+//! fixed header followed by its payload:
+//!
+//! | bytes | field                                       |
+//! |-------|---------------------------------------------|
+//! | 4     | network magic (`ChainParams::magic`)        |
+//! | 1     | wire version                                |
+//! | 1     | message type                                |
+//! | 4     | payload length, big-endian                  |
+//! | 4     | checksum: first 4 bytes of sha256(payload)  |
+
+use crate::{
+    read_array, read_field, read_header, write_header, write_text_field, Address, Block,
+    Transaction, FIELD_TEXT, MAX_BLOCK_BYTES, MAX_BLOCK_TXS,
+};
+use anyhow::{anyhow, ensure, Result};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+
+pub const WIRE_VERSION: u8 = 1;
+/// Bytes before the payload.
+pub const HEADER_LEN: usize = 14;
+/// Largest payload a peer may announce; a block is the biggest message.
+pub const MAX_PAYLOAD: usize = MAX_BLOCK_BYTES;
+
+const TYPE_BLOCK: u8 = 1;
+const TYPE_TX: u8 = 2;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Block(Block),
+    Tx(Transaction),
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let digest = Sha256::digest(payload);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+fn write_tx(w: &mut impl Write, tx: &Transaction) -> Result<()> {
+    write_text_field(w, FIELD_TEXT, tx.from.as_str())?;
+    write_text_field(w, FIELD_TEXT, tx.to.as_str())?;
+    w.write_all(&tx.amount.to_be_bytes())?;
+    Ok(())
+}
+
+fn read_tx(r: &mut impl Read) -> Result<Transaction> {
+    Ok(Transaction {
+        from: Address::parse(&read_field(r)?)?,
+        to: Address::parse(&read_field(r)?)?,
+        amount: u64::from_be_bytes(read_array(r)?),
+    })
+}
+
+/// Block payloads omit the hash; the receiver recomputes it from the
+/// header.
+fn encode_payload(msg: &Message) -> Result<(u8, Vec<u8>)> {
+    let mut payload = Vec::new();
+    let kind = match msg {
+        Message::Block(block) => {
+            write_header(&mut payload, &block.header)?;
+            payload.extend_from_slice(&(block.txs.len() as u32).to_be_bytes());
+            for tx in &block.txs {
+                write_tx(&mut payload, tx)?;
+            }
+            TYPE_BLOCK
+        }
+        Message::Tx(tx) => {
+            write_tx(&mut payload, tx)?;
+            TYPE_TX
+        }
+    };
+    Ok((kind, payload))
+}
+
+fn decode_payload(kind: u8, mut payload: &[u8]) -> Result<Message> {
+    let r = &mut payload;
+    let msg = match kind {
+        TYPE_BLOCK => {
+            let header = read_header(r)?.ok_or_else(|| anyhow!("block message has no header"))?;
+            let count = u32::from_be_bytes(read_array(r)?) as usize;
+            ensure!(
+                count <= MAX_BLOCK_TXS,
+                "block message carries {} transactions, the limit is {}",
+                count,
+                MAX_BLOCK_TXS
+            );
+            let txs = (0..count).map(|_| read_tx(r)).collect::<Result<Vec<_>>>()?;
+            let hash = crate::hash_block_header(&header);
+            Message::Block(Block { header, txs, hash })
+        }
+        TYPE_TX => Message::Tx(read_tx(r)?),
+        other => return Err(anyhow!("unknown message type {}", other)),
+    };
+    ensure!(r.is_empty(), "{} trailing bytes after message", r.len());
+    Ok(msg)
+}
+
+pub fn write_message(w: &mut impl Write, msg: &Message, magic: [u8; 4]) -> Result<()> {
+    let (kind, payload) = encode_payload(msg)?;
+    ensure!(
+        payload.len() <= MAX_PAYLOAD,
+        "payload is {} bytes, the limit is {}",
+        payload.len(),
+        MAX_PAYLOAD
+    );
+    w.write_all(&magic)?;
+    w.write_all(&[WIRE_VERSION, kind])?;
+    w.write_all(&(payload.len() as u32).to_be_bytes())?;
+    w.write_all(&checksum(&payload))?;
+    w.write_all(&payload)?;
+    Ok(())
+}
+
+/// Reads one framed message, rejecting other networks, other versions,
+/// oversized payloads and checksum mismatches before decoding the payload.
+pub fn read_message(r: &mut impl Read, magic: [u8; 4]) -> Result<Message> {
+    let header: [u8; HEADER_LEN] = read_array(r)?;
+    ensure!(
+        header[..4] == magic,
+        "message magic {} is not this network's {}",
+        hex::encode(&header[..4]),
+        hex::encode(magic)
+    );
+    ensure!(
+        header[4] == WIRE_VERSION,
+        "unsupported wire version {}",
+        header[4]
+    );
+    let len = u32::from_be_bytes(header[6..10].try_into().unwrap()) as usize;
+    ensure!(
+        len <= MAX_PAYLOAD,
+        "payload is {} bytes, the limit is {}",
+        len,
+        MAX_PAYLOAD
+    );
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+    ensure!(
+        checksum(&payload) == header[10..14],
+        "message checksum does not match its payload"
+    );
+    decode_payload(header[5], &payload)
+}
+
+pub fn encode(msg: &Message, magic: [u8; 4]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write_message(&mut out, msg, magic)?;
+    Ok(out)
+}
+
+pub fn decode(mut bytes: &[u8], magic: [u8; 4]) -> Result<Message> {
+    let msg = read_message(&mut bytes, magic)?;
+    ensure!(bytes.is_empty(), "{} bytes after the message", bytes.len());
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_block, make_genesis, ChainParams, NonceStride};
+
+    fn sample_block(params: &ChainParams) -> Block {
+        let tx = Transaction {
+            from: Address::parse("alice").unwrap(),
+            to: Address::parse("bob").unwrap(),
+            amount: 7,
+        };
+        let genesis = make_genesis(params);
+        build_block(
+            &genesis,
+            vec![tx.clone(), tx],
+            Address::parse("miner").unwrap(),
+            3,
+            genesis.header.timestamp + 10,
+            params.difficulty,
+            params,
+        )
+        .unwrap()
+        .mine(NonceStride::default())
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let params = ChainParams::testnet();
+        let block = sample_block(&params);
+        let bytes = encode(&Message::Block(block.clone()), params.magic).unwrap();
+        assert_eq!(&bytes[..4], b"POWT");
+        let Message::Block(decoded) = decode(&bytes, params.magic).unwrap() else {
+            panic!("expected a block");
+        };
+        assert_eq!(decoded.hash, block.hash);
+        assert_eq!(decoded.txs, block.txs);
+        assert_eq!(
+            serde_json::to_value(&decoded.header).unwrap(),
+            serde_json::to_value(&block.header).unwrap()
+        );
+
+        let tx = block.txs[0].clone();
+        let bytes = encode(&Message::Tx(tx.clone()), params.magic).unwrap();
+        assert_eq!(bytes.len(), HEADER_LEN + (2 + 5) + (2 + 3) + 8);
+        match decode(&bytes, params.magic).unwrap() {
+            Message::Tx(decoded) => assert_eq!(decoded, tx),
+            other => panic!("expected a tx, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn corrupted_frames_are_rejected() {
+        let params = ChainParams::testnet();
+        let good = encode(&Message::Block(sample_block(&params)), params.magic).unwrap();
+        let reject = |bytes: &[u8], expected: &str| {
+            let err = decode(bytes, params.magic).unwrap_err();
+            assert!(
+                err.to_string().contains(expected),
+                "{}: {:#}",
+                expected,
+                err
+            );
+        };
+
+        let mut bad = good.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        reject(&bad, "checksum");
+        let mut bad = good.clone();
+        bad[10] ^= 1;
+        reject(&bad, "checksum");
+        assert!(decode(&good, params.magic).is_ok());
+        let err = decode(&good, *b"POWM").unwrap_err();
+        assert!(err.to_string().contains("magic"), "{:#}", err);
+        let mut bad = good.clone();
+        bad[4] = WIRE_VERSION + 1;
+        reject(&bad, "wire version");
+        let mut bad = good.clone();
+        bad[6..10].copy_from_slice(&(MAX_PAYLOAD as u32 + 1).to_be_bytes());
+        reject(&bad, "limit");
+        let mut bad = good.clone();
+        bad.push(0);
+        reject(&bad, "after the message");
+        assert!(decode(&good[..good.len() - 1], params.magic).is_err());
+    }
+
+    #[test]
+    fn oversized_length_prefix_is_refused_before_reading_the_payload() {
+        let magic = ChainParams::testnet().magic;
+        let mut frame = Vec::from(magic);
+        frame.extend_from_slice(&[WIRE_VERSION, TYPE_BLOCK]);
+        frame.extend_from_slice(&u32::MAX.to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        // what a peer might send after the header; none of it is read
+        frame.extend_from_slice(&[0xab; 64]);
+
+        let mut r = &frame[..];
+        let err = read_message(&mut r, magic).unwrap_err();
+        assert!(err.to_string().contains("limit"), "{:#}", err);
+        assert_eq!(r.len(), 64);
+    }
+}